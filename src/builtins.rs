@@ -0,0 +1,125 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::callable::NativeFnc;
+use crate::diagnostic::Diagnostic;
+use crate::enviro::Enviro;
+use crate::expr::map_key;
+use crate::literals::LiteralVal::*;
+use crate::literals::LiteralVal;
+use crate::scanner::Token;
+
+// Puts every builtin into the global scope
+pub fn define_builtins(enviro: &mut Enviro) {
+    let natives = [
+        NativeFnc {
+            name: "len",
+            arity: 1,
+            fun: len,
+        },
+        NativeFnc {
+            name: "keys",
+            arity: 1,
+            fun: keys,
+        },
+        NativeFnc {
+            name: "get",
+            arity: 3,
+            fun: get,
+        },
+    ];
+    for n in natives {
+        enviro.define(n.name.to_string(), NativeVal(Rc::new(n)));
+    }
+}
+
+// len(x) -> item count of a list/map, char count of a string
+fn len(args: Vec<LiteralVal>, paren: &Token) -> Result<LiteralVal, Diagnostic> {
+    match &args[0] {
+        ListVal(l) => Ok(IntVal(l.borrow().len() as i64)),
+        MapVal(m) => Ok(IntVal(m.borrow().len() as i64)),
+        StringVal(s) => Ok(IntVal(s.chars().count() as i64)),
+        other => Err(Diagnostic::error(
+            412,
+            format!("len() does not work on a {}", other.as_literal_type()),
+            paren.span,
+        )),
+    }
+}
+
+// keys(map) -> list of keys in the order they were added
+fn keys(args: Vec<LiteralVal>, paren: &Token) -> Result<LiteralVal, Diagnostic> {
+    match &args[0] {
+        MapVal(m) => {
+            let ks = m.borrow().entries().iter().map(|(k, _)| k.to_literal()).collect();
+            Ok(ListVal(Rc::new(RefCell::new(ks))))
+        }
+        other => Err(Diagnostic::error(
+            412,
+            format!("keys() needs a Map, got a {}", other.as_literal_type()),
+            paren.span,
+        )),
+    }
+}
+
+// get(map, key, default) -> value at key, or default when it is missing
+fn get(args: Vec<LiteralVal>, paren: &Token) -> Result<LiteralVal, Diagnostic> {
+    let map = match &args[0] {
+        MapVal(m) => Rc::clone(m),
+        other => {
+            return Err(Diagnostic::error(
+                412,
+                format!("get() needs a Map, got a {}", other.as_literal_type()),
+                paren.span,
+            ))
+        }
+    };
+    let key = map_key(&args[1], paren.span)?;
+    let found = map.borrow().get(&key).cloned();
+    Ok(found.unwrap_or_else(|| args[2].clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    // Evaluates src with only the builtins in scope
+    fn eval(src: &str) -> Result<LiteralVal, Diagnostic> {
+        let mut globals = Enviro::new();
+        define_builtins(&mut globals);
+        let tokens = Scanner::new(src).scan_tokens().unwrap();
+        let expr = Parser::new(tokens).expression()?;
+        expr.eval(&Rc::new(RefCell::new(globals)))
+    }
+    fn show(src: &str) -> String {
+        eval(src).unwrap().format_str()
+    }
+
+    #[test]
+    fn len_counts_items_and_chars() {
+        assert_eq!(show("len([1, [2, 3]])"), "2");
+        assert_eq!(show("len({\"a\": 1, \"b\": 2})"), "2");
+        assert_eq!(show("len(\"h\u{e9}llo\")"), "5");
+        assert_eq!(show("len(\"\")"), "0");
+        let e = eval("len(1)").err().unwrap();
+        assert_eq!((e.code, e.msg.as_str()), (412, "len() does not work on a Int"));
+    }
+
+    #[test]
+    fn keys_come_out_in_insertion_order() {
+        assert_eq!(show("keys({\"b\": 1, 2: 2, \"a\": 3, 0.5: 4})"), "[\"b\", 2, \"a\", 0.5]");
+        assert_eq!(show("keys({})"), "[]");
+        assert_eq!(eval("keys([1])").err().unwrap().code, 412);
+    }
+
+    #[test]
+    fn get_falls_back_to_the_default() {
+        assert_eq!(show("get({\"a\": 1}, \"a\", 0)"), "1");
+        assert_eq!(show("get({\"a\": 1}, \"b\", 0)"), "0");
+        // 1.0 finds the entry stored under 1
+        assert_eq!(show("get({1: \"one\"}, 1.0, null)"), "one");
+        assert_eq!(eval("get([1], 0, 0)").err().unwrap().code, 412);
+        assert_eq!(eval("get({}, [1], 0)").err().unwrap().code, 409);
+    }
+}
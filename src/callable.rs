@@ -0,0 +1,202 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::diagnostic::Diagnostic;
+use crate::enviro::Enviro;
+use crate::interpreter::Interpreter;
+use crate::literals::LiteralVal;
+use crate::scanner::Token;
+use crate::statement::Statement;
+
+// How deep fnc/cls calls can nest before the script is stopped (keeps the host stack safe)
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+thread_local! {
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// Runs a call one level deeper, errors instead of overflowing the stack on runaway recursion
+pub fn nested_call(
+    paren: &Token,
+    call: impl FnOnce() -> Result<LiteralVal, Diagnostic>,
+) -> Result<LiteralVal, Diagnostic> {
+    let depth = CALL_DEPTH.with(|d| d.get());
+    if depth >= MAX_CALL_DEPTH {
+        return Err(Diagnostic::error(
+            415,
+            format!("Stack overflow, calls nested more than {} deep", MAX_CALL_DEPTH),
+            paren.span,
+        )
+        .with_note("check that the recursion reaches a case that stops it"));
+    }
+    CALL_DEPTH.with(|d| d.set(depth + 1));
+    let res = call();
+    CALL_DEPTH.with(|d| d.set(depth));
+    res
+}
+
+// User defined function made by a fnc declaration (or a method in a cls)
+pub struct Function {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Rc<Vec<Statement>>,
+    pub closure: Rc<RefCell<Enviro>>,
+    pub is_init: bool,
+}
+
+impl Function {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+    // Runs the body in a new scope on top of the closure with the params bound to the args
+    pub fn call(&self, args: Vec<LiteralVal>) -> Result<LiteralVal, Diagnostic> {
+        let mut scope = Enviro::new_enclosed(Rc::clone(&self.closure));
+        for (param, arg) in self.params.iter().zip(args) {
+            scope.define(param.lexeme.clone(), arg);
+        }
+        let mut intr = Interpreter::with_enviro(Rc::new(RefCell::new(scope)));
+        let v = intr.execute_function_body(&self.body)?;
+        // init always hands back the instance, even on a bare ret
+        if self.is_init {
+            return Ok(self.closure.borrow().get_at(0, 0).unwrap_or(LiteralVal::NullVal));
+        }
+        Ok(v)
+    }
+    // Copy of the method with `this` sitting in slot 0 of a scope just above the body
+    pub fn bind(&self, instance: Rc<RefCell<Instance>>) -> Function {
+        let mut scope = Enviro::new_enclosed(Rc::clone(&self.closure));
+        scope.define("this".to_string(), LiteralVal::InstanceVal(instance));
+        Function {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            body: Rc::clone(&self.body),
+            closure: Rc::new(RefCell::new(scope)),
+            is_init: self.is_init,
+        }
+    }
+}
+
+// Functions are only equal to themselves
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fnc {}>", self.name.lexeme)
+    }
+}
+
+// Made by a cls declaration, calling it makes a new instance
+pub struct Class {
+    pub name: String,
+    pub superclass: Option<Rc<Class>>,
+    pub methods: HashMap<String, Rc<Function>>,
+}
+
+impl Class {
+    // Own methods first, then up the superclass chain
+    pub fn find_method(&self, name: &str) -> Option<Rc<Function>> {
+        match self.methods.get(name) {
+            Some(m) => Some(Rc::clone(m)),
+            None => match &self.superclass {
+                Some(sup) => sup.find_method(name),
+                None => None,
+            },
+        }
+    }
+    // Takes the same args as init (none if there is no init)
+    pub fn arity(&self) -> usize {
+        match self.find_method("init") {
+            Some(init) => init.arity(),
+            None => 0,
+        }
+    }
+    pub fn instantiate(class: &Rc<Class>, args: Vec<LiteralVal>) -> Result<LiteralVal, Diagnostic> {
+        let instance = Rc::new(RefCell::new(Instance {
+            class: Rc::clone(class),
+            fields: HashMap::new(),
+        }));
+        if let Some(init) = class.find_method("init") {
+            init.bind(Rc::clone(&instance)).call(args)?;
+        }
+        Ok(LiteralVal::InstanceVal(instance))
+    }
+}
+
+impl PartialEq for Class {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<cls {}>", self.name)
+    }
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: HashMap<String, LiteralVal>,
+}
+
+impl Instance {
+    // Fields win over methods, methods come back bound to this instance
+    pub fn get(instance: &Rc<RefCell<Instance>>, name: &Token) -> Result<LiteralVal, Diagnostic> {
+        if let Some(v) = instance.borrow().fields.get(&name.lexeme) {
+            return Ok(v.clone());
+        }
+        let method = instance.borrow().class.find_method(&name.lexeme);
+        match method {
+            Some(m) => Ok(LiteralVal::FncVal(Rc::new(m.bind(Rc::clone(instance))))),
+            None => Err(Diagnostic::error(
+                405,
+                format!(
+                    "Undefined property -=({})=- on {} instance",
+                    name.lexeme,
+                    instance.borrow().class.name
+                ),
+                name.span,
+            )),
+        }
+    }
+    pub fn set(&mut self, name: &Token, val: LiteralVal) {
+        self.fields.insert(name.lexeme.clone(), val);
+    }
+}
+
+// Instances are only equal to themselves
+impl PartialEq for Instance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
+    }
+}
+
+// Function built into the language (see builtins.rs)
+pub struct NativeFnc {
+    pub name: &'static str,
+    pub arity: usize,
+    pub fun: fn(Vec<LiteralVal>, &Token) -> Result<LiteralVal, Diagnostic>,
+}
+
+impl PartialEq for NativeFnc {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for NativeFnc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fnc {}>", self.name)
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use crate::scanner::Span;
+
+// Error codes by stage:
+//   E01xx scanner, E02xx parser, E03xx resolver, E04xx runtime
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// Extra span pointed at alongside the main one (ex "first declared here")
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub msg: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // shown as E0401 etc
+    pub code: u16,
+    pub msg: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: u16, msg: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            msg: msg.into(),
+            span,
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+    pub fn with_label(mut self, span: Span, msg: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            msg: msg.into(),
+        });
+        self
+    }
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    // rustc style snippet, carets under the main span and dashes under the labels:
+    //
+    // error[E0401]: Cannot use Plus between Number and String
+    //  --> script.md:2:11
+    //   |
+    // 2 | print a + "x";
+    //   |         ^
+    pub fn render(&self, source: &str, file: &str) -> String {
+        let mut out = format!("{}[E{:04}]: {}\n", self.severity, self.code, self.msg);
+        // synthetic spans (line 0) have nothing to point at
+        if self.span.line == 0 {
+            for n in &self.notes {
+                out.push_str(&format!("  = note: {}\n", n));
+            }
+            return out;
+        }
+
+        let mut marks: Vec<(Span, char, &str)> = vec![(self.span, '^', "")];
+        for l in &self.labels {
+            if l.span.line != 0 && source_line(source, l.span.offset).is_some() {
+                marks.push((l.span, '-', &l.msg));
+            }
+        }
+        marks.sort_by_key(|(s, _, _)| (s.line, s.col));
+        let width = marks.iter().map(|(s, _, _)| s.line).max().unwrap_or(1).to_string().len();
+        let pad = " ".repeat(width);
+
+        out.push_str(&format!("{}--> {}:{}:{}\n", pad, file, self.span.line, self.span.col));
+        // a span from some other source only gets the location, never a slice at a bad offset
+        if source_line(source, self.span.offset).is_none() {
+            for n in &self.notes {
+                out.push_str(&format!("{} = note: {}\n", pad, n));
+            }
+            return out;
+        }
+        out.push_str(&format!("{} |\n", pad));
+        let mut last_line = 0;
+        for (span, mark, msg) in marks {
+            let Some(text) = source_line(source, span.offset) else {
+                continue;
+            };
+            if span.line != last_line {
+                out.push_str(&format!("{:>w$} | {}\n", span.line, text, w = width));
+                last_line = span.line;
+            }
+            out.push_str(&format!("{} | {}\n", pad, underline(text, span, mark, msg)));
+        }
+        for n in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", pad, n));
+        }
+        out
+    }
+}
+
+// The full line of source holding the byte offset, None if the offset is not a char boundary in it
+fn source_line(source: &str, offset: usize) -> Option<&str> {
+    if !source.is_char_boundary(offset) {
+        return None;
+    }
+    let start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let end = source[offset..].find('\n').map(|i| offset + i).unwrap_or(source.len());
+    Some(source[start..end].trim_end_matches('\r'))
+}
+
+// Marks under the span, tabs are copied so the marks line up with the source above
+fn underline(line: &str, span: Span, mark: char, msg: &str) -> String {
+    let mut out = String::new();
+    for c in line.chars().take(span.col.saturating_sub(1)) {
+        out.push(if c == '\t' { '\t' } else { ' ' });
+    }
+    // spans running past the end of the line only get marked up to the end of it
+    let rest: String = line.chars().skip(span.col.saturating_sub(1)).collect();
+    let count = rest
+        .char_indices()
+        .take_while(|(i, _)| *i < span.len)
+        .count()
+        .max(1);
+    out.push_str(&mark.to_string().repeat(count));
+    if !msg.is_empty() {
+        out.push(' ');
+        out.push_str(msg);
+    }
+    out
+}
+
+// Closest candidate to a misspelled name, None when nothing is close enough to be a typo
+pub fn did_you_mean<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    let mut best: Option<(usize, &str)> = None;
+    for c in candidates {
+        if c == name {
+            continue;
+        }
+        let d = edit_distance(name, c);
+        // ties go to the alphabetically first name so hints are the same every run
+        let better = match best {
+            Some((bd, bc)) => d < bd || (d == bd && c < bc),
+            None => true,
+        };
+        if d <= limit && better {
+            best = Some((d, c));
+        }
+    }
+    best.map(|(_, c)| c)
+}
+
+// Levenshtein distance, counted in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur.push(sub.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// Every problem found by one stage, so they can all be reported at once
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push(&mut self, d: Diagnostic) {
+        self.items.push(d);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.items.iter()
+    }
+    pub fn render(&self, source: &str, file: &str) -> String {
+        let parts: Vec<String> = self.items.iter().map(|d| d.render(source, file)).collect();
+        parts.join("\n")
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(d: Diagnostic) -> Self {
+        Self { items: vec![d] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Span of the first `find` in source (1 based line/col like the scanner makes)
+    fn span_of(source: &str, find: &str) -> Span {
+        let offset = source.find(find).unwrap();
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        Span {
+            offset,
+            len: find.len(),
+            line,
+            col: source[line_start..offset].chars().count() + 1,
+        }
+    }
+
+    #[test]
+    fn snippet_with_label_and_note() {
+        let src = "let x = 1;\nprint x + \"a\";\n";
+        let d = Diagnostic::error(401, "Cannot add", span_of(src, "+"))
+            .with_label(span_of(src, "\"a\""), "String")
+            .with_note("convert it first");
+        let want = "error[E0401]: Cannot add\n \
+                    --> t.md:2:9\n  \
+                    |\n\
+                    2 | print x + \"a\";\n  \
+                    |         ^\n  \
+                    |           --- String\n  \
+                    = note: convert it first\n";
+        assert_eq!(d.render(src, "t.md"), want);
+    }
+
+    #[test]
+    fn labels_on_other_lines_come_in_line_order() {
+        let src = "{\n  let a = 1;\n  let a = 2;\n}";
+        let first = span_of(src, "a = 1");
+        let d = Diagnostic::error(307, "dup", span_of(src, "a = 2"))
+            .with_label(Span { len: 1, ..first }, "first declared here");
+        let out = d.render(src, "t.md");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[3], "2 |   let a = 1;");
+        assert_eq!(lines[4], "  |       - first declared here");
+        assert_eq!(lines[5], "3 |   let a = 2;");
+        assert_eq!(lines[6], "  |       ^^^^^");
+    }
+
+    #[test]
+    fn marks_line_up_under_tabs_and_multi_byte_text() {
+        let src = "\tprint \"éé\" + x;";
+        let out = Diagnostic::error(1, "m", span_of(src, "x")).render(src, "t");
+        assert!(out.contains("\n  | \t             ^\n"), "{}", out);
+        // a span running past the end of its line is cut at the line end
+        let src = "ab\ncd";
+        let out = Diagnostic::error(1, "m", Span { len: 10, ..span_of(src, "b") }).render(src, "t");
+        assert!(out.contains("\n  |  ^\n"), "{}", out);
+    }
+
+    #[test]
+    fn spans_without_source_skip_the_snippet() {
+        // synthetic span
+        let d = Diagnostic::error(207, "stop", Span::default()).with_note("n");
+        assert_eq!(d.render("x", "t"), "error[E0207]: stop\n  = note: n\n");
+        // offset past the end, or inside a multi-byte char, of the source given
+        let src = "print \"é\";";
+        for offset in [src.len() + 5, src.find('é').unwrap() + 1] {
+            let span = Span {
+                offset,
+                len: 1,
+                line: 1,
+                col: 3,
+            };
+            let d = Diagnostic::error(1, "m", span_of(src, "print")).with_label(span, "gone");
+            assert!(!d.render(src, "t").contains("gone"));
+            let d = Diagnostic::error(1, "m", span);
+            assert_eq!(d.render(src, "t"), "error[E0001]: m\n --> t:1:3\n");
+        }
+    }
+
+    #[test]
+    fn many_diagnostics_are_split_by_a_blank_line() {
+        let mut all = Diagnostics::new();
+        all.push(Diagnostic::error(1, "a", Span::default()));
+        all.push(Diagnostic::error(2, "b", Span::default()));
+        assert_eq!(all.render("", "t"), "error[E0001]: a\n\nerror[E0002]: b\n");
+    }
+
+    #[test]
+    fn did_you_mean_picks_close_names() {
+        let names = ["count", "counter", "total"];
+        assert_eq!(did_you_mean("cont", names.iter().copied()), Some("count"));
+        assert_eq!(did_you_mean("totl", names.iter().copied()), Some("total"));
+        assert_eq!(did_you_mean("zzz", names.iter().copied()), None);
+        // ties go alphabetically
+        assert_eq!(did_you_mean("ab", ["ac", "aa"].into_iter()), Some("aa"));
+    }
+}
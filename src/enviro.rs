@@ -1,23 +1,117 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use crate::diagnostic::Diagnostic;
 use crate::literals::LiteralVal;
+use crate::scanner::Token;
 
+// Variable scope, blocks get their own enviro that points back to the outer one.
+// Shared (Rc<RefCell>) so closures keep the scope they were made in alive.
+// Globals are looked up by name, locals live in slots the resolver picked ahead of time
 pub struct Enviro {
-     vals:  HashMap<String, LiteralVal>,
+    vals: HashMap<String, LiteralVal>,
+    slots: Vec<LiteralVal>,
+    // name of each slot, only read for "did you mean" hints
+    names: Vec<String>,
+    enclosing: Option<Rc<RefCell<Enviro>>>,
 }
 
 impl Enviro {
     pub fn new() -> Self {
         Self {
-            vals: HashMap::new()
+            vals: HashMap::new(),
+            slots: Vec::new(),
+            names: Vec::new(),
+            enclosing: None,
+        }
+    }
+    // New inner scope wrapping an outer one (blocks and function calls)
+    pub fn new_enclosed(enclosing: Rc<RefCell<Enviro>>) -> Self {
+        Self {
+            vals: HashMap::new(),
+            slots: Vec::new(),
+            names: Vec::new(),
+            enclosing: Some(enclosing),
         }
     }
 
+    // Global scope stores by name, local scopes take the next slot (same order the resolver counted)
     pub fn define(&mut self, name: String, val: LiteralVal) {
-        self.vals.insert(name, val);
+        match self.enclosing {
+            None => {
+                self.vals.insert(name, val);
+            }
+            Some(_) => {
+                self.slots.push(val);
+                self.names.push(name);
+            }
+        }
+    }
+
+    // Every name visible from this scope, innermost first
+    pub fn names(&self) -> Vec<String> {
+        match &self.enclosing {
+            Some(e) => {
+                let mut out = self.names.clone();
+                out.extend(e.borrow().names());
+                out
+            }
+            None => self.vals.keys().cloned().collect(),
+        }
     }
 
-    pub fn get(&self, name: &str) -> Option<&LiteralVal> {
-        self.vals.get(name)
+    // Global lookup, walks out to the outermost scope
+    pub fn get(&self, name: &str) -> Option<LiteralVal> {
+        match &self.enclosing {
+            Some(e) => e.borrow().get(name),
+            None => self.vals.get(name).cloned(),
+        }
     }
 
+    // Local lookup, depth scopes out then straight to the slot
+    pub fn get_at(&self, depth: usize, slot: usize) -> Option<LiteralVal> {
+        if depth == 0 {
+            return self.slots.get(slot).cloned();
+        }
+        match &self.enclosing {
+            Some(e) => e.borrow().get_at(depth - 1, slot),
+            None => None,
+        }
+    }
+
+    // Reassigns an existing global
+    pub fn assign(&mut self, name: &Token, val: LiteralVal) -> Result<(), Diagnostic> {
+        if let Some(e) = &self.enclosing {
+            return e.borrow_mut().assign(name, val);
+        }
+        match self.vals.get_mut(&name.lexeme) {
+            Some(v) => {
+                *v = val;
+                Ok(())
+            }
+            // the caller adds the "did you mean", only it can see the locals too
+            None => Err(Diagnostic::error(
+                402,
+                format!("Cannot assign to -=({})=-, it has not been declared!", name.lexeme),
+                name.span,
+            )),
+        }
+    }
+
+    // Reassigns an existing local
+    pub fn assign_at(&mut self, depth: usize, slot: usize, val: LiteralVal) -> bool {
+        if depth == 0 {
+            return match self.slots.get_mut(slot) {
+                Some(v) => {
+                    *v = val;
+                    true
+                }
+                None => false,
+            };
+        }
+        match &self.enclosing {
+            Some(e) => e.borrow_mut().assign_at(depth - 1, slot, val),
+            None => false,
+        }
+    }
 }
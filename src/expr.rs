@@ -1,10 +1,15 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
-use crate::literals::LiteralVal;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use crate::callable::{nested_call, Class, Instance};
+use crate::diagnostic::{did_you_mean, Diagnostic};
 use crate::literals::LiteralVal::*;
+use crate::literals::{LiteralVal, MapKey, MidasMap};
 use crate::enviro::Enviro;
-use crate::scanner::{Token, TokenType};
+use crate::resolver::Slot;
+use crate::scanner::{foreign_kw, Span, Token, TokenType};
 
 // AST expression implementation
 pub enum Expr {
@@ -18,13 +23,114 @@ pub enum Expr {
     },
     Literal {
         val: LiteralVal,
+        span: Span,
     },
     Unary {
         op: Token,
         r: Box<Expr>,
     },
+    // slot is filled in by the resolver, None means a global
     Variable {
         name: Token,
+        slot: Cell<Option<Slot>>,
+    },
+    // update is set for a += 1, a++ ... (same for Set and IndexSet)
+    Assign {
+        name: Token,
+        val: Box<Expr>,
+        slot: Cell<Option<Slot>>,
+        update: Option<Update>,
+    },
+    Logical {
+        l: Box<Expr>,
+        op: Token,
+        r: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        args: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        val: Box<Expr>,
+        update: Option<Update>,
+    },
+    This {
+        kw: Token,
+        slot: Cell<Option<Slot>>,
+    },
+    // "a ${x} b", parts are the string pieces and the inner exprs in order
+    Interpolation {
+        parts: Vec<Expr>,
+    },
+    List {
+        span: Span,
+        items: Vec<Expr>,
+    },
+    Map {
+        span: Span,
+        entries: Vec<(Expr, Expr)>,
+    },
+    // object[index], bracket is the closing ] for error lines
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    IndexSet {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        val: Box<Expr>,
+        update: Option<Update>,
+    },
+    // slot points at `super`, `this` always sits one scope further in
+    Super {
+        kw: Token,
+        method: Token,
+        slot: Cell<Option<Slot>>,
+    },
+}
+
+// The op of `a += v` and `a++`, applied to the target's old value and val before storing.
+// The target (a, a.b, a[i]) is only evaluated once, postfix a++ hands back the old value
+pub struct Update {
+    pub op: Token,
+    pub postfix: bool,
+}
+
+impl Update {
+    // (value to store, value the expression gives back), old is only read for an update
+    fn apply(
+        update: &Option<Update>,
+        old: impl FnOnce() -> Result<LiteralVal, Diagnostic>,
+        target: Span,
+        val: &Expr,
+        enviro: &Rc<RefCell<Enviro>>,
+    ) -> Result<(LiteralVal, LiteralVal), Diagnostic> {
+        let u = match update {
+            Some(u) => u,
+            None => {
+                let v = val.eval(enviro)?;
+                return Ok((v.clone(), v));
+            }
+        };
+        let old = old()?;
+        let new = binary(old.clone(), &u.op, val.eval(enviro)?, target, val.span())?;
+        if u.postfix {
+            Ok((new, old))
+        } else {
+            Ok((new.clone(), new))
+        }
+    }
+    fn lexeme(update: &Option<Update>) -> &str {
+        update.as_ref().map_or("=", |u| u.op.lexeme.as_str())
     }
 }
 
@@ -36,9 +142,89 @@ impl Expr {
                 format!("({} {} {})", op.lexeme, l.format_str(), r.format_str())
             }
             Expr::Grouping { expr } => format!("(group {})", expr.format_str()),
-            Expr::Literal { val } => format!("{}", val.format_str()),
+            Expr::Literal { val, .. } => val.format_str(),
             Expr::Unary { op, r } => format!("({} {})", op.lexeme, r.format_str()),
-            Expr::Variable { name } => format!("var {}", name.lexeme),
+            Expr::Variable { name, .. } => format!("var {}", name.lexeme),
+            Expr::Assign {
+                name, val, update, ..
+            } => format!("({} {} {})", Update::lexeme(update), name.lexeme, val.format_str()),
+            Expr::Logical { l, op, r } => {
+                format!("({} {} {})", op.lexeme, l.format_str(), r.format_str())
+            }
+            Expr::Call { callee, args, .. } => {
+                let args: Vec<String> = args.iter().map(|a| a.format_str()).collect();
+                format!("(call {} {})", callee.format_str(), args.join(" "))
+            }
+            Expr::Get { object, name } => format!("(. {} {})", object.format_str(), name.lexeme),
+            Expr::Set {
+                object,
+                name,
+                val,
+                update,
+            } => format!(
+                "({} (. {} {}) {})",
+                Update::lexeme(update),
+                object.format_str(),
+                name.lexeme,
+                val.format_str()
+            ),
+            Expr::This { .. } => "this".to_string(),
+            Expr::Super { method, .. } => format!("(super {})", method.lexeme),
+            Expr::Interpolation { parts } => {
+                let parts: Vec<String> = parts.iter().map(|p| p.format_str()).collect();
+                format!("(interp {})", parts.join(" "))
+            }
+            Expr::List { items, .. } => {
+                let items: Vec<String> = items.iter().map(|i| i.format_str()).collect();
+                format!("(list {})", items.join(" "))
+            }
+            Expr::Map { entries, .. } => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(k, v)| format!("({} {})", k.format_str(), v.format_str()))
+                    .collect();
+                format!("(map {})", entries.join(" "))
+            }
+            Expr::Index { object, index, .. } => {
+                format!("([] {} {})", object.format_str(), index.format_str())
+            }
+            Expr::IndexSet {
+                object,
+                index,
+                val,
+                update,
+                ..
+            } => format!(
+                "({} ([] {} {}) {})",
+                Update::lexeme(update),
+                object.format_str(),
+                index.format_str(),
+                val.format_str()
+            ),
+        }
+    }
+    // Source covered by the whole expression
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary { l, r, .. } | Expr::Logical { l, r, .. } => l.span().to(r.span()),
+            Expr::Grouping { expr } => expr.span(),
+            Expr::Literal { span, .. } | Expr::List { span, .. } | Expr::Map { span, .. } => *span,
+            Expr::Unary { op, r } => op.span.to(r.span()),
+            Expr::Variable { name, .. } => name.span,
+            Expr::Assign { name, val, .. } => name.span.to(val.span()),
+            Expr::Call { callee, paren, .. } => callee.span().to(paren.span),
+            Expr::Get { object, name } => object.span().to(name.span),
+            Expr::Set { object, val, .. } => object.span().to(val.span()),
+            Expr::This { kw, .. } => kw.span,
+            Expr::Super { kw, method, .. } => kw.span.to(method.span),
+            Expr::Interpolation { parts } => match (parts.first(), parts.last()) {
+                (Some(f), Some(l)) => f.span().to(l.span()),
+                _ => Span::default(),
+            },
+            Expr::Index {
+                object, bracket, ..
+            } => object.span().to(bracket.span),
+            Expr::IndexSet { object, val, .. } => object.span().to(val.span()),
         }
     }
     // Prints structure of syntax tree (useful for debugging)
@@ -46,104 +232,666 @@ impl Expr {
         println!("{}", self.format_str());
     }
     // This acts as my interpeter, evaluates expressions
-    pub fn eval(&self, enviro: &Enviro) -> Result<LiteralVal, String> {
+    pub fn eval(&self, enviro: &Rc<RefCell<Enviro>>) -> Result<LiteralVal, Diagnostic> {
         match self {
-            Expr::Variable { name } => match enviro.get(&name.lexeme) {
-                Some(v) => Ok(v.clone()),
-                None => Err(format!("Variable -=({})=- has not been declared!", name.lexeme))
-            },
-            Expr::Literal { val } => Ok(val.clone()),
-            Expr::Grouping { expr } => expr.eval(enviro),
-            Expr::Unary { op, r } => {
-                let r = r.eval(enviro)?;
-                match (op.token_type, r.clone()) {
-                    (TokenType::Minus, NumVal(x)) => Ok(NumVal(-x)),
-                    // TODO: add for floats
-                    (TokenType::Minus, _) => Err(format!(
-                        "Cannot use Minus operator on type {}",
-                        r.as_literal_type()
-                    )),
-                    (TokenType::Bang, any) => Ok(any.is_falsy()),
-                    (tt, _) => Err(format!("{} is not a valid operator for unaries", tt)),
+            Expr::Variable { name, slot } => lookup(name, slot, enviro),
+            // Assignment is an expression so it hands back the value (a = b = 3)
+            Expr::Assign {
+                name,
+                val,
+                slot,
+                update,
+            } => {
+                let (v, res) =
+                    Update::apply(update, || lookup(name, slot, enviro), name.span, val, enviro)?;
+                match slot.get() {
+                    Some(s) => {
+                        if !enviro.borrow_mut().assign_at(s.depth, s.index, v) {
+                            return Err(Diagnostic::error(
+                                402,
+                                format!(
+                                    "Cannot assign to -=({})=-, it has not been declared!",
+                                    name.lexeme
+                                ),
+                                name.span,
+                            ));
+                        }
+                    }
+                    None => {
+                        let assigned = enviro.borrow_mut().assign(name, v);
+                        if let Err(err) = assigned {
+                            return Err(match similar_name(name, enviro) {
+                                Some(s) => err.with_note(format!("did you mean `{}`?", s)),
+                                None => err.with_note("declare it first with let"),
+                            });
+                        }
+                    }
                 }
+                Ok(res)
             }
-            // TODO: Keep adding stuff for this
-            Expr::Binary { l, op, r } => {
+            // Short circuits and hands back whichever side decided it (not a bool)
+            Expr::Logical { l, op, r } => {
                 let l = l.eval(enviro)?;
-                let r = r.eval(enviro)?;
-                match (l.clone(), op.token_type, r.clone()) {
-                    (NumVal(x), TokenType::Plus, NumVal(y)) => Ok(NumVal(x + y)),
-                    (NumVal(x), TokenType::Minus, NumVal(y)) => Ok(NumVal(x - y)),
-                    (NumVal(x), TokenType::Slash, NumVal(y)) => {
-                        if y != 0.0 {
-                            Ok(NumVal(x / y))
-                        } else {
-                            Err(format!(
-                                "Cannot divide -=({})=- by 0, results in infinity",
-                                x
-                            ))
+                match op.token_type {
+                    TokenType::Or if l.is_truthy() => Ok(l),
+                    TokenType::And if !l.is_truthy() => Ok(l),
+                    _ => r.eval(enviro),
+                }
+            }
+            Expr::Call {
+                callee,
+                paren,
+                args,
+            } => {
+                let callee_span = callee.span();
+                let callee = callee.eval(enviro)?;
+                // args run left to right before the call
+                let mut vals = Vec::new();
+                for a in args {
+                    vals.push(a.eval(enviro)?);
+                }
+                match callee {
+                    FncVal(f) => {
+                        if vals.len() != f.arity() {
+                            return Err(Diagnostic::error(
+                                404,
+                                format!(
+                                    "Expected {} arguments but got {} when calling -=({})=-",
+                                    f.arity(),
+                                    vals.len(),
+                                    f.name.lexeme
+                                ),
+                                paren.span,
+                            )
+                            .with_label(f.name.span, "defined here"));
                         }
+                        nested_call(paren, || f.call(vals))
                     }
-                    (NumVal(x), TokenType::Star, NumVal(y)) => Ok(NumVal(x * y)),
-
-                    (NumVal(x), TokenType::Greater, NumVal(y)) => {
-                        Ok(LiteralVal::is_boolean_truthy(x > y))
-                    }
-                    (NumVal(x), TokenType::GreaterEqual, NumVal(y)) => {
-                        Ok(LiteralVal::is_boolean_truthy(x >= y))
+                    ClassVal(c) => {
+                        if vals.len() != c.arity() {
+                            let mut err = Diagnostic::error(
+                                404,
+                                format!(
+                                    "Expected {} arguments but got {} when making -=({})=-",
+                                    c.arity(),
+                                    vals.len(),
+                                    c.name
+                                ),
+                                paren.span,
+                            );
+                            if let Some(init) = c.find_method("init") {
+                                err = err.with_label(init.name.span, "init defined here");
+                            }
+                            return Err(err);
+                        }
+                        nested_call(paren, || Class::instantiate(&c, vals))
                     }
-                    (NumVal(x), TokenType::Less, NumVal(y)) => {
-                        Ok(LiteralVal::is_boolean_truthy(x < y))
+                    NativeVal(n) => {
+                        if vals.len() != n.arity {
+                            return Err(Diagnostic::error(
+                                404,
+                                format!(
+                                    "Expected {} arguments but got {} when calling -=({})=-",
+                                    n.arity,
+                                    vals.len(),
+                                    n.name
+                                ),
+                                paren.span,
+                            ));
+                        }
+                        (n.fun)(vals, paren)
                     }
-                    (NumVal(x), TokenType::LessEqual, NumVal(y)) => {
-                        Ok(LiteralVal::is_boolean_truthy(x <= y))
+                    other => Err(Diagnostic::error(
+                        403,
+                        format!(
+                            "Can only call functions and classes, -=({})=- is a {}",
+                            other.format_str(),
+                            other.as_literal_type()
+                        ),
+                        paren.span,
+                    )
+                    .with_label(callee_span, "this is not callable")),
+                }
+            }
+            Expr::Get { object, name } => match object.eval(enviro)? {
+                InstanceVal(i) => Instance::get(&i, name),
+                other => Err(Diagnostic::error(
+                    405,
+                    format!(
+                        "Only instances have properties, cannot get -=({})=- from a {}",
+                        name.lexeme,
+                        other.as_literal_type()
+                    ),
+                    name.span,
+                )),
+            },
+            Expr::Set {
+                object,
+                name,
+                val,
+                update,
+            } => match object.eval(enviro)? {
+                InstanceVal(i) => {
+                    let (v, res) =
+                        Update::apply(update, || Instance::get(&i, name), name.span, val, enviro)?;
+                    i.borrow_mut().set(name, v);
+                    Ok(res)
+                }
+                other => Err(Diagnostic::error(
+                    405,
+                    format!(
+                        "Only instances have fields, cannot set -=({})=- on a {}",
+                        name.lexeme,
+                        other.as_literal_type()
+                    ),
+                    name.span,
+                )),
+            },
+            Expr::This { kw, slot } => {
+                let v = match slot.get() {
+                    Some(s) => enviro.borrow().get_at(s.depth, s.index),
+                    None => None,
+                };
+                match v {
+                    Some(v) => Ok(v),
+                    None => Err(Diagnostic::error(
+                        305,
+                        "-=(this)=- is not inside a method",
+                        kw.span,
+                    )),
+                }
+            }
+            Expr::Super { kw, method, slot } => {
+                let (sup, this) = match slot.get() {
+                    Some(s) if s.depth > 0 => {
+                        let env = enviro.borrow();
+                        (env.get_at(s.depth, s.index), env.get_at(s.depth - 1, 0))
                     }
-
-                    (StringVal(s), TokenType::Plus, StringVal(s2)) => {
-                        Ok(StringVal(format!("{}{}", s, s2)))
+                    _ => (None, None),
+                };
+                match (sup, this) {
+                    (Some(ClassVal(sup)), Some(InstanceVal(this))) => {
+                        match sup.find_method(&method.lexeme) {
+                            Some(m) => Ok(FncVal(Rc::new(m.bind(this)))),
+                            None => Err(Diagnostic::error(
+                                405,
+                                format!(
+                                    "Undefined property -=({})=- on superclass {}",
+                                    method.lexeme, sup.name
+                                ),
+                                method.span,
+                            )),
+                        }
                     }
-                    (StringVal(s), TokenType::Greater, StringVal(s2)) => {
-                        Ok(LiteralVal::is_boolean_truthy(s > s2))
+                    _ => Err(Diagnostic::error(
+                        306,
+                        "-=(super)=- is not inside a subclass method",
+                        kw.span,
+                    )),
+                }
+            }
+            Expr::Interpolation { parts } => {
+                let mut out = String::new();
+                for p in parts {
+                    out.push_str(&p.eval(enviro)?.format_str());
+                }
+                Ok(StringVal(out))
+            }
+            Expr::List { items, .. } => {
+                let mut vals = Vec::new();
+                for i in items {
+                    vals.push(i.eval(enviro)?);
+                }
+                Ok(ListVal(Rc::new(RefCell::new(vals))))
+            }
+            // keys and values run in source order
+            Expr::Map { entries, .. } => {
+                let mut map = MidasMap::new();
+                for (k, v) in entries {
+                    let key = map_key(&k.eval(enviro)?, k.span())?;
+                    map.insert(key, v.eval(enviro)?);
+                }
+                Ok(MapVal(Rc::new(RefCell::new(map))))
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let (object_span, index_span) = (object.span(), index.span());
+                let object = object.eval(enviro)?;
+                let index = index.eval(enviro)?;
+                match object {
+                    ListVal(l) => {
+                        let l = l.borrow();
+                        let i = list_index(l.len(), &index, index_span)?;
+                        Ok(l[i].clone())
                     }
-                    (StringVal(s), TokenType::GreaterEqual, StringVal(s2)) => {
-                        Ok(LiteralVal::is_boolean_truthy(s >= s2))
+                    MapVal(m) => {
+                        let key = map_key(&index, index_span)?;
+                        match m.borrow().get(&key) {
+                            Some(v) => Ok(v.clone()),
+                            None => Err(missing_key(&index, index_span)),
+                        }
                     }
-                    (StringVal(s), TokenType::Less, StringVal(s2)) => {
-                        Ok(LiteralVal::is_boolean_truthy(s < s2))
+                    other => Err(Diagnostic::error(
+                        408,
+                        format!("Cannot index into a {}", other.as_literal_type()),
+                        bracket.span,
+                    )
+                    .with_label(object_span, other.as_literal_type())),
+                }
+            }
+            Expr::IndexSet {
+                object,
+                bracket,
+                index,
+                val,
+                update,
+            } => {
+                let (object_span, index_span) = (object.span(), index.span());
+                let target = object_span.to(bracket.span);
+                let object = object.eval(enviro)?;
+                let index = index.eval(enviro)?;
+                match object {
+                    ListVal(l) => {
+                        let old = || {
+                            let l = l.borrow();
+                            Ok(l[list_index(l.len(), &index, index_span)?].clone())
+                        };
+                        let (v, res) = Update::apply(update, old, target, val, enviro)?;
+                        // val can change the list so the index is checked again
+                        let mut l = l.borrow_mut();
+                        let i = list_index(l.len(), &index, index_span)?;
+                        l[i] = v;
+                        Ok(res)
                     }
-                    (StringVal(s), TokenType::LessEqual, StringVal(s2)) => {
-                        Ok(LiteralVal::is_boolean_truthy(s <= s2))
+                    MapVal(m) => {
+                        let key = map_key(&index, index_span)?;
+                        let old = || match m.borrow().get(&key) {
+                            Some(v) => Ok(v.clone()),
+                            None => Err(missing_key(&index, index_span)),
+                        };
+                        let (v, res) = Update::apply(update, old, target, val, enviro)?;
+                        m.borrow_mut().insert(key, v);
+                        Ok(res)
                     }
-                    // UNCOMMENT IF YOU WANT TO TREAT NUM + STR OPERATIONS AS VALID
-
-                    //                     (NumVal(x), TokenType::Plus, StringVal(s)) => {
-                    //                         let x2 = x.to_string();
-                    //                         Ok(StringVal(format!("{}{}", x2, s)))
-                    //                     }
-                    //                     (StringVal(s), TokenType::Plus, NumVal(x)) => {
-                    //                         let x2 = x.to_string();
-                    //                         Ok(StringVal(format!("{}{}", x2, s)))
-                    //                     }
-                    (NumVal(x), op, StringVal(s)) => Err(format!(
-                        "Cannot use {} operater between Number and String types -=({} and {})=-",
-                        op, x, s
-                    )),
-                    (StringVal(s), op, NumVal(x)) => Err(format!(
-                        "Cannot use {} operater between String and Number types -=({} and {})=-",
-                        op, s, x
+                    other => Err(Diagnostic::error(
+                        408,
+                        format!("Cannot index into a {}", other.as_literal_type()),
+                        bracket.span,
+                    )
+                    .with_label(object_span, other.as_literal_type())),
+                }
+            }
+            Expr::Literal { val, .. } => Ok(val.clone()),
+            Expr::Grouping { expr } => expr.eval(enviro),
+            Expr::Unary { op, r } => {
+                let r_span = r.span();
+                let r = r.eval(enviro)?;
+                match (op.token_type, r.clone()) {
+                    (TokenType::Minus, NumVal(x)) => Ok(NumVal(-x)),
+                    (TokenType::Minus, IntVal(x)) => match x.checked_neg() {
+                        Some(v) => Ok(IntVal(v)),
+                        None => Err(overflow(format!("-{}", x), op.span.to(r_span))),
+                    },
+                    (TokenType::Minus, _) => Err(Diagnostic::error(
+                        401,
+                        format!("Cannot use Minus operator on type {}", r.as_literal_type()),
+                        op.span,
                     )),
-
-                    (x, TokenType::BangEqual, y) => Ok(LiteralVal::is_boolean_truthy(x != y)),
-                    (x, TokenType::EqualEqual, y) => Ok(LiteralVal::is_boolean_truthy(x == y)),
-                    (x, op, y) => Err(format!(
-                        "{} not yet implemented for -=({:?} and {:?})=-",
-                        op,
-                        x.as_literal_type(),
-                        y.as_literal_type()
+                    (TokenType::Bang, any) => Ok(any.is_falsy()),
+                    (TokenType::Tilde, IntVal(x)) => Ok(IntVal(!x)),
+                    (TokenType::Tilde, _) => Err(Diagnostic::error(
+                        401,
+                        format!("Bitwise -=(~)=- only works on Ints, got {}", r.as_literal_type()),
+                        op.span,
+                    )
+                    .with_label(r_span, r.as_literal_type())),
+                    (tt, _) => Err(Diagnostic::error(
+                        401,
+                        format!("{} is not a valid operator for unaries", tt),
+                        op.span,
                     )),
                 }
             }
+            // TODO: Keep adding stuff for this
+            Expr::Binary { l, op, r } => {
+                let (l_span, r_span) = (l.span(), r.span());
+                let l = l.eval(enviro)?;
+                let r = r.eval(enviro)?;
+                binary(l, op, r, l_span, r_span)
+            }
+        }
+    }
+}
+
+// Value of a variable, through its resolved slot or by name for globals
+fn lookup(
+    name: &Token,
+    slot: &Cell<Option<Slot>>,
+    enviro: &Rc<RefCell<Enviro>>,
+) -> Result<LiteralVal, Diagnostic> {
+    let v = match slot.get() {
+        Some(s) => enviro.borrow().get_at(s.depth, s.index),
+        None => enviro.borrow().get(&name.lexeme),
+    };
+    match v {
+        Some(v) => Ok(v),
+        None => {
+            let err = Diagnostic::error(
+                402,
+                format!("Variable -=({})=- has not been declared!", name.lexeme),
+                name.span,
+            );
+            let hint = foreign_kw(&name.lexeme)
+                .map(|kw| kw.to_string())
+                .or_else(|| similar_name(name, enviro));
+            match hint {
+                Some(h) => Err(err.with_note(format!("did you mean `{}`?", h))),
+                None => Err(err),
+            }
+        }
+    }
+}
+
+// Closest name visible from this scope, locals included, for "did you mean" hints
+fn similar_name(name: &Token, enviro: &Rc<RefCell<Enviro>>) -> Option<String> {
+    let names = enviro.borrow().names();
+    did_you_mean(&name.lexeme, names.iter().map(|n| n.as_str())).map(|s| s.to_string())
+}
+
+fn missing_key(index: &LiteralVal, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        407,
+        format!("Key -=({})=- not found in map", index.format_str()),
+        span,
+    )
+    .with_note("use get(map, key, default) for a fallback")
+}
+
+// Binary operators on two values, also used by += and friends on the target's old value
+fn binary(
+    l: LiteralVal,
+    op: &Token,
+    r: LiteralVal,
+    l_span: Span,
+    r_span: Span,
+) -> Result<LiteralVal, Diagnostic> {
+    let at = op.span;
+    // Int with Int stays an Int, a Float on either side makes it a Float
+    if let (IntVal(x), IntVal(y)) = (&l, &r) {
+        if let Some(res) = int_binary(*x, op.token_type, *y, at, r_span) {
+            return res;
+        }
+    } else if let (Some(x), Some(y)) = (l.as_f64(), r.as_f64()) {
+        if let Some(res) = float_binary(x, op.token_type, y, at, r_span) {
+            return res;
+        }
+    }
+    match (l.clone(), op.token_type, r.clone()) {
+        (
+            x,
+            TokenType::Amp
+            | TokenType::Pipe
+            | TokenType::Caret
+            | TokenType::LessLess
+            | TokenType::GreaterGreater,
+            y,
+        ) => Err(Diagnostic::error(
+            401,
+            format!(
+                "Bitwise -=({})=- only works on Ints, got {} and {}",
+                op.lexeme,
+                x.as_literal_type(),
+                y.as_literal_type()
+            ),
+            at,
+        )
+        .with_label(l_span, x.as_literal_type())
+        .with_label(r_span, y.as_literal_type())),
+        (StringVal(s), TokenType::Plus, StringVal(s2)) => {
+            Ok(StringVal(format!("{}{}", s, s2)))
         }
+        (StringVal(s), TokenType::Greater, StringVal(s2)) => {
+            Ok(LiteralVal::is_boolean_truthy(s > s2))
+        }
+        (StringVal(s), TokenType::GreaterEqual, StringVal(s2)) => {
+            Ok(LiteralVal::is_boolean_truthy(s >= s2))
+        }
+        (StringVal(s), TokenType::Less, StringVal(s2)) => {
+            Ok(LiteralVal::is_boolean_truthy(s < s2))
+        }
+        (StringVal(s), TokenType::LessEqual, StringVal(s2)) => {
+            Ok(LiteralVal::is_boolean_truthy(s <= s2))
+        }
+        // UNCOMMENT IF YOU WANT TO TREAT NUM + STR OPERATIONS AS VALID
+
+        //                     (NumVal(x), TokenType::Plus, StringVal(s)) => {
+        //                         let x2 = x.to_string();
+        //                         Ok(StringVal(format!("{}{}", x2, s)))
+        //                     }
+        //                     (StringVal(s), TokenType::Plus, NumVal(x)) => {
+        //                         let x2 = x.to_string();
+        //                         Ok(StringVal(format!("{}{}", x2, s)))
+        //                     }
+        (x, op, StringVal(s)) if x.as_f64().is_some() => Err(Diagnostic::error(
+            401,
+            format!(
+                "Cannot use {} operater between {} and String types -=({} and {})=-",
+                op,
+                x.as_literal_type(),
+                x.format_str(),
+                s
+            ),
+            at,
+        )
+        .with_label(l_span, x.as_literal_type())
+        .with_label(r_span, "String")),
+        (StringVal(s), op, x) if x.as_f64().is_some() => Err(Diagnostic::error(
+            401,
+            format!(
+                "Cannot use {} operater between String and {} types -=({} and {})=-",
+                op,
+                x.as_literal_type(),
+                s,
+                x.format_str()
+            ),
+            at,
+        )
+        .with_label(l_span, "String")
+        .with_label(r_span, x.as_literal_type())),
+
+        (x, TokenType::BangEqual, y) => Ok(LiteralVal::is_boolean_truthy(x != y)),
+        (x, TokenType::EqualEqual, y) => Ok(LiteralVal::is_boolean_truthy(x == y)),
+        (x, op, y) => Err(Diagnostic::error(
+            401,
+            format!(
+                "{} not yet implemented for -=({:?} and {:?})=-",
+                op,
+                x.as_literal_type(),
+                y.as_literal_type()
+            ),
+            at,
+        )
+        .with_label(l_span, x.as_literal_type())
+        .with_label(r_span, y.as_literal_type())),
+    }
+}
+
+// Math and comparisons on two ints, None for operators that are not numeric (==, !=...).
+// Overflow is an error instead of wrapping, / truncates toward zero like C (7 / 2 == 3, -7 / 2 == -3)
+// while // floors (-7 // 2 == -4) and % takes the sign of the right side so x == (x // y) * y + x % y
+fn int_binary(
+    x: i64,
+    op: TokenType,
+    y: i64,
+    at: Span,
+    r_span: Span,
+) -> Option<Result<LiteralVal, Diagnostic>> {
+    let checked = |v: Option<i64>, sym: &str| match v {
+        Some(v) => Ok(IntVal(v)),
+        None => Err(overflow(format!("{} {} {}", x, sym, y), at)),
+    };
+    let res = match op {
+        TokenType::Plus => checked(x.checked_add(y), "+"),
+        TokenType::Minus => checked(x.checked_sub(y), "-"),
+        TokenType::Star => checked(x.checked_mul(y), "*"),
+        TokenType::Slash if y == 0 => Err(divide_by_zero(&IntVal(x), at, r_span)),
+        TokenType::Slash => checked(x.checked_div(y), "/"),
+        TokenType::SlashSlash | TokenType::Percent if y == 0 => {
+            Err(divide_by_zero(&IntVal(x), at, r_span))
+        }
+        TokenType::SlashSlash => {
+            // the remainder being off in sign means truncating went up, step back down
+            let q = x.checked_div(y);
+            let fix = x.wrapping_rem(y) != 0 && (x < 0) != (y < 0);
+            checked(q.map(|q| if fix { q - 1 } else { q }), "//")
+        }
+        TokenType::Percent => {
+            let r = x.wrapping_rem(y);
+            Ok(IntVal(if r != 0 && (r < 0) != (y < 0) { r + y } else { r }))
+        }
+        TokenType::StarStar => int_pow(x, y, at),
+        TokenType::Amp => Ok(IntVal(x & y)),
+        TokenType::Pipe => Ok(IntVal(x | y)),
+        TokenType::Caret => Ok(IntVal(x ^ y)),
+        TokenType::LessLess | TokenType::GreaterGreater => shift(x, op, y, at, r_span),
+        TokenType::Greater => Ok(LiteralVal::is_boolean_truthy(x > y)),
+        TokenType::GreaterEqual => Ok(LiteralVal::is_boolean_truthy(x >= y)),
+        TokenType::Less => Ok(LiteralVal::is_boolean_truthy(x < y)),
+        TokenType::LessEqual => Ok(LiteralVal::is_boolean_truthy(x <= y)),
+        _ => return None,
+    };
+    Some(res)
+}
+
+// << and >> by 0..=63 bits, >> keeps the sign. Bits shifted out of the top are dropped like C,
+// but an amount outside the range is an error instead of being undefined
+fn shift(x: i64, op: TokenType, y: i64, at: Span, r_span: Span) -> Result<LiteralVal, Diagnostic> {
+    let res = u32::try_from(y).ok().and_then(|n| match op {
+        TokenType::LessLess => x.checked_shl(n),
+        _ => x.checked_shr(n),
+    });
+    match res {
+        Some(v) => Ok(IntVal(v)),
+        None => Err(Diagnostic::error(
+            414,
+            format!("Shift amount -=({})=- is out of range", y),
+            at,
+        )
+        .with_label(r_span, "has to be 0 to 63")),
+    }
+}
+
+// Same as int_binary once either side is a Float (the other one gets promoted)
+fn float_binary(
+    x: f64,
+    op: TokenType,
+    y: f64,
+    at: Span,
+    r_span: Span,
+) -> Option<Result<LiteralVal, Diagnostic>> {
+    let res = match op {
+        TokenType::Plus => Ok(NumVal(x + y)),
+        TokenType::Minus => Ok(NumVal(x - y)),
+        TokenType::Star => Ok(NumVal(x * y)),
+        TokenType::Slash if y == 0.0 => Err(divide_by_zero(&NumVal(x), at, r_span)),
+        TokenType::Slash => Ok(NumVal(x / y)),
+        TokenType::SlashSlash | TokenType::Percent if y == 0.0 => {
+            Err(divide_by_zero(&NumVal(x), at, r_span))
+        }
+        TokenType::SlashSlash => Ok(NumVal((x / y).floor())),
+        TokenType::Percent => {
+            let r = x % y;
+            Ok(NumVal(if r != 0.0 && (r < 0.0) != (y < 0.0) { r + y } else { r }))
+        }
+        TokenType::StarStar if x == 0.0 && y < 0.0 => Err(zero_to_negative(at)),
+        TokenType::StarStar => Ok(NumVal(x.powf(y))),
+        TokenType::Greater => Ok(LiteralVal::is_boolean_truthy(x > y)),
+        TokenType::GreaterEqual => Ok(LiteralVal::is_boolean_truthy(x >= y)),
+        TokenType::Less => Ok(LiteralVal::is_boolean_truthy(x < y)),
+        TokenType::LessEqual => Ok(LiteralVal::is_boolean_truthy(x <= y)),
+        _ => return None,
+    };
+    Some(res)
+}
+
+// Int ** Int, a negative power can not stay whole so that one gives a Float (2 ** -1 == 0.5)
+fn int_pow(x: i64, y: i64, at: Span) -> Result<LiteralVal, Diagnostic> {
+    if y < 0 {
+        if x == 0 {
+            return Err(zero_to_negative(at));
+        }
+        return Ok(NumVal((x as f64).powf(y as f64)));
+    }
+    let v = match u32::try_from(y) {
+        Ok(e) => x.checked_pow(e),
+        // only 0, 1 and -1 survive a power that big
+        Err(_) => match x {
+            0 | 1 => Some(x),
+            -1 => Some(if y % 2 == 0 { 1 } else { -1 }),
+            _ => None,
+        },
+    };
+    match v {
+        Some(v) => Ok(IntVal(v)),
+        None => Err(overflow(format!("{} ** {}", x, y), at)),
+    }
+}
+
+fn zero_to_negative(at: Span) -> Diagnostic {
+    Diagnostic::error(406, "Cannot raise 0 to a negative power, results in infinity", at)
+}
+
+fn divide_by_zero(x: &LiteralVal, at: Span, r_span: Span) -> Diagnostic {
+    Diagnostic::error(406, format!("Cannot divide -=({})=- by 0", x.format_str()), at)
+        .with_label(r_span, "this is 0")
+}
+
+fn overflow(expr: String, at: Span) -> Diagnostic {
+    Diagnostic::error(413, format!("Integer overflow in -=({})=-", expr), at)
+        .with_note(format!(
+            "Ints go from {} to {}, use a Float (ex 1.0) for bigger numbers",
+            i64::MIN,
+            i64::MAX
+        ))
+}
+
+// Checks a list index is a whole number in range, negative counts back from the end
+fn list_index(len: usize, index: &LiteralVal, span: Span) -> Result<usize, Diagnostic> {
+    let i = match index {
+        IntVal(i) => *i,
+        NumVal(x) if x.fract() == 0.0 => *x as i64,
+        other => {
+            return Err(Diagnostic::error(
+                408,
+                format!("List index must be a whole number, got -=({})=-", other.format_str()),
+                span,
+            ))
+        }
+    };
+    let real = if i < 0 { i + len as i64 } else { i };
+    if real < 0 || real >= len as i64 {
+        return Err(Diagnostic::error(
+            408,
+            format!("List index -=({})=- out of bounds for length {}", i, len),
+            span,
+        )
+        .with_note("negative indexes count back from the end, -1 is the last item"));
+    }
+    Ok(real as usize)
+}
+
+// Only strings and numbers can be map keys
+pub fn map_key(key: &LiteralVal, span: Span) -> Result<MapKey, Diagnostic> {
+    match MapKey::from_literal(key) {
+        Some(k) => Ok(k),
+        None => Err(Diagnostic::error(
+            409,
+            format!("Map keys must be a String or Number, got a {}", key.as_literal_type()),
+            span,
+        )),
     }
 }
 
@@ -216,3 +964,297 @@ impl Expr {
 // //         Ok(())
 // //     }
 // // }
+
+// Evaluating expressions: Int stays Int, Float is contagious, overflow and / 0 are errors,
+// plus logic/bitwise ops, interpolation, list/map indexing and assignment
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn eval(src: &str) -> Result<LiteralVal, Diagnostic> {
+        eval_in(src, &Rc::new(RefCell::new(Enviro::new())))
+    }
+    fn eval_in(src: &str, enviro: &Rc<RefCell<Enviro>>) -> Result<LiteralVal, Diagnostic> {
+        let tokens = Scanner::new(src).scan_tokens().unwrap();
+        let expr = Parser::new(tokens).expression()?;
+        expr.eval(enviro)
+    }
+    fn err_code(src: &str) -> u16 {
+        eval(src).err().unwrap().code
+    }
+
+    #[test]
+    fn int_literals_make_ints() {
+        assert!(matches!(eval("1 + 2"), Ok(IntVal(3))));
+        assert!(matches!(eval("16777217"), Ok(IntVal(16777217))));
+        assert!(matches!(eval("2.5"), Ok(NumVal(x)) if x == 2.5));
+    }
+
+    #[test]
+    fn floats_are_64_bit() {
+        // 16777217 is the first int an f32 cannot hold
+        assert!(matches!(eval("16777216.0 + 1.0"), Ok(NumVal(x)) if x == 16777217.0));
+    }
+
+    #[test]
+    fn mixed_math_promotes_to_float() {
+        assert!(matches!(eval("1 + 2.5"), Ok(NumVal(x)) if x == 3.5));
+        assert!(matches!(eval("2 * 1.5"), Ok(NumVal(x)) if x == 3.0));
+        assert!(matches!(eval("7.0 / 2"), Ok(NumVal(x)) if x == 3.5));
+        assert_eq!(eval("2 * 1.5").unwrap().format_str(), "3.0");
+    }
+
+    #[test]
+    fn int_division_truncates_toward_zero() {
+        assert!(matches!(eval("7 / 2"), Ok(IntVal(3))));
+        assert!(matches!(eval("-7 / 2"), Ok(IntVal(-3))));
+        assert!(matches!(eval("7 / -2"), Ok(IntVal(-3))));
+        assert!(matches!(eval("-7 / -2"), Ok(IntVal(3))));
+        assert!(matches!(eval("6 / 3"), Ok(IntVal(2))));
+    }
+
+    #[test]
+    fn divide_by_zero_is_an_error() {
+        assert_eq!(err_code("1 / 0"), 406);
+        assert_eq!(err_code("1.0 / 0"), 406);
+    }
+
+    #[test]
+    fn int_overflow_is_an_error() {
+        assert_eq!(err_code("9223372036854775807 + 1"), 413);
+        assert_eq!(err_code("-9223372036854775807 - 2"), 413);
+        assert_eq!(err_code("4611686018427387904 * 2"), 413);
+        assert_eq!(err_code("(-9223372036854775807 - 1) / -1"), 413);
+        assert_eq!(err_code("-(-9223372036854775807 - 1)"), 413);
+        // the same math in floats just gets less precise
+        assert!(eval("9223372036854775807.0 + 1").is_ok());
+    }
+
+    #[test]
+    fn modulo_takes_the_sign_of_the_right_side() {
+        assert!(matches!(eval("7 % 3"), Ok(IntVal(1))));
+        assert!(matches!(eval("-7 % 3"), Ok(IntVal(2))));
+        assert!(matches!(eval("7 % -3"), Ok(IntVal(-2))));
+        assert!(matches!(eval("-7 % -3"), Ok(IntVal(-1))));
+        assert!(matches!(eval("-7.5 % 2"), Ok(NumVal(x)) if x == 0.5));
+        assert_eq!(err_code("1 % 0"), 406);
+    }
+
+    #[test]
+    fn floor_division_rounds_down() {
+        assert!(matches!(eval("7 // 2"), Ok(IntVal(3))));
+        assert!(matches!(eval("-7 // 2"), Ok(IntVal(-4))));
+        assert!(matches!(eval("7 // -2"), Ok(IntVal(-4))));
+        assert!(matches!(eval("7.5 // 2"), Ok(NumVal(x)) if x == 3.0));
+        assert_eq!(err_code("1 // 0"), 406);
+        assert_eq!(err_code("(-9223372036854775807 - 1) // -1"), 413);
+        // x == (x // y) * y + x % y for every sign combination
+        for x in -9..=9 {
+            for y in [-4, -3, 3, 4] {
+                let src = format!("({x} // {y}) * {y} + {x} % {y}");
+                assert!(matches!(eval(&src), Ok(IntVal(v)) if v == x));
+            }
+        }
+    }
+
+    #[test]
+    fn power_binds_tighter_than_unary_minus() {
+        assert!(matches!(eval("2 ** 10"), Ok(IntVal(1024))));
+        assert!(matches!(eval("-2 ** 2"), Ok(IntVal(-4))));
+        assert!(matches!(eval("(-2) ** 2"), Ok(IntVal(4))));
+        assert!(matches!(eval("2 ** -1"), Ok(NumVal(x)) if x == 0.5));
+        assert!(matches!(eval("2 ** 3 ** 2"), Ok(IntVal(512))));
+        assert!(matches!(eval("10 - 2 ** 3 * 2"), Ok(IntVal(-6))));
+        assert_eq!(err_code("2 ** 64"), 413);
+        assert_eq!(err_code("0 ** -1"), 406);
+    }
+
+    #[test]
+    fn ints_and_floats_compare_by_value() {
+        assert!(matches!(eval("1 == 1.0"), Ok(TrueVal)));
+        assert!(matches!(eval("2 > 1.5"), Ok(TrueVal)));
+        assert!(matches!(eval("[1, 2] == [1.0, 2.0]"), Ok(TrueVal)));
+    }
+
+    #[test]
+    fn bitwise_ops_on_ints() {
+        assert!(matches!(eval("12 & 10"), Ok(IntVal(8))));
+        assert!(matches!(eval("12 | 10"), Ok(IntVal(14))));
+        assert!(matches!(eval("12 ^ 10"), Ok(IntVal(6))));
+        assert!(matches!(eval("~5"), Ok(IntVal(-6))));
+        assert!(matches!(eval("1 << 62"), Ok(IntVal(4611686018427387904))));
+        assert!(matches!(eval("-16 >> 2"), Ok(IntVal(-4))));
+        // C precedence: shift < additive, & < ==, then ^ then |
+        assert!(matches!(eval("1 + 1 << 2"), Ok(IntVal(8))));
+        assert!(matches!(eval("1 | 2 ^ 3 & 4"), Ok(IntVal(3))));
+        assert!(matches!(eval("1 << 2 < 5"), Ok(TrueVal)));
+        assert_eq!(err_code("1 << 64"), 414);
+        assert_eq!(err_code("1 >> -1"), 414);
+    }
+
+    #[test]
+    fn bitwise_ops_reject_other_types() {
+        assert_eq!(err_code("1.0 & 1"), 401);
+        assert_eq!(err_code("\"a\" | 1"), 401);
+        assert_eq!(err_code("true ^ false"), 401);
+        assert_eq!(err_code("~1.5"), 401);
+        // x & 1 == 1 is x & (1 == 1) like C, so it is a type error and not a silent bug
+        let err = eval("3 & 1 == 1").err().unwrap();
+        assert_eq!(err.msg, "Bitwise -=(&)=- only works on Ints, got Int and Boolean");
+        let err = eval("null & 1").err().unwrap();
+        assert_eq!(err.msg, "Bitwise -=(&)=- only works on Ints, got Null and Int");
+    }
+
+    #[test]
+    fn assignment_is_right_associative() {
+        let enviro = Rc::new(RefCell::new(Enviro::new()));
+        for name in ["a", "b"] {
+            enviro.borrow_mut().define(name.to_string(), NullVal);
+        }
+        let run = |src: &str| eval_in(src, &enviro).unwrap().format_str();
+        assert_eq!(run("a = b = 3"), "3");
+        assert_eq!(run("a + b"), "6");
+        assert_eq!(run("a = (b = 4) + 1"), "5");
+        assert_eq!(run("b"), "4");
+    }
+
+    #[test]
+    fn assigning_an_undeclared_name() {
+        let e = eval("\n  nope = 2").err().unwrap();
+        assert_eq!(e.code, 402);
+        assert_eq!(e.msg, "Cannot assign to -=(nope)=-, it has not been declared!");
+        assert_eq!((e.span.line, e.span.col, e.span.len), (2, 3, 4));
+    }
+
+    #[test]
+    fn logical_ops_give_back_the_deciding_operand() {
+        assert_eq!(eval("null or \"d\"").unwrap().format_str(), "d");
+        assert!(matches!(eval("0 and x"), Ok(IntVal(0))));
+        assert!(matches!(eval("\"\" or 0"), Ok(IntVal(0))));
+        assert!(matches!(eval("2 and 3.5"), Ok(NumVal(x)) if x == 3.5));
+        assert!(matches!(eval("1 or x"), Ok(IntVal(1))));
+        assert!(matches!(eval("false or null"), Ok(NullVal)));
+    }
+
+    #[test]
+    fn logical_ops_short_circuit() {
+        let enviro = Rc::new(RefCell::new(Enviro::new()));
+        enviro.borrow_mut().define("x".to_string(), IntVal(0));
+        let run = |src: &str| eval_in(src, &enviro).unwrap().format_str();
+        // the right side would be an error, or change x, if it ran
+        assert_eq!(run("true or 1 / 0"), "true");
+        assert_eq!(run("null and undeclared"), "null");
+        assert_eq!(run("false and (x = 1)"), "false");
+        assert_eq!(run("1 or (x = 2)"), "1");
+        assert_eq!(run("x"), "0");
+        assert_eq!(run("true and (x = 3)"), "3");
+        assert_eq!(err_code("false or 1 / 0"), 406);
+    }
+
+    #[test]
+    fn interpolation_formats_each_value() {
+        assert_eq!(eval("\"${1 + 2}|${2.0}|${0.5}\"").unwrap().format_str(), "3|2.0|0.5");
+        assert_eq!(eval("\"${[1, \"a\", null]}\"").unwrap().format_str(), "[1, \"a\", null]");
+        assert_eq!(eval("\"${ {\"k\": [true]} }\"").unwrap().format_str(), "{\"k\": [true]}");
+        // strings go in without quotes, nested interpolation works
+        assert_eq!(eval("\"<${\"in${1}\"}>\"").unwrap().format_str(), "<in1>");
+        assert!(matches!(eval("\"${1}\""), Ok(StringVal(_))));
+    }
+
+    #[test]
+    fn negative_indexes_count_from_the_end() {
+        assert!(matches!(eval("[1, 2, 3][-1]"), Ok(IntVal(3))));
+        assert!(matches!(eval("[1, 2, 3][-3]"), Ok(IntVal(1))));
+        assert!(matches!(eval("[1, 2, 3][1.0]"), Ok(IntVal(2))));
+    }
+
+    #[test]
+    fn bad_list_indexes() {
+        for (src, i) in [("[1, 2, 3][3]", "3"), ("[1, 2, 3][-4]", "-4"), ("[][0]", "0")] {
+            let e = eval(src).err().unwrap();
+            assert_eq!(e.code, 408, "{}", src);
+            assert!(e.msg.starts_with(&format!("List index -=({})=- out of bounds", i)), "{}", src);
+        }
+        for src in ["[1][0.5]", "[1][\"0\"]", "[1][null]"] {
+            let e = eval(src).err().unwrap();
+            assert_eq!(e.code, 408, "{}", src);
+            assert!(e.msg.starts_with("List index must be a whole number"), "{}", src);
+        }
+        assert_eq!(eval("[1, 2][2] = 0").err().unwrap().code, 408);
+        assert_eq!(err_code("1[0]"), 408);
+    }
+
+    #[test]
+    fn lists_are_shared_between_names() {
+        let enviro = Rc::new(RefCell::new(Enviro::new()));
+        for name in ["a", "b"] {
+            enviro.borrow_mut().define(name.to_string(), NullVal);
+        }
+        let run = |src: &str| eval_in(src, &enviro).unwrap().format_str();
+        run("a = [1, 2]");
+        run("b = a");
+        run("b[0] = 10");
+        assert_eq!(run("a"), "[10, 2]");
+        // a fresh literal is a new list, not an alias
+        run("b = [10, 2]");
+        run("b[1] = 0");
+        assert_eq!(run("a"), "[10, 2]");
+        assert_eq!(run("a == [10, 2]"), "true");
+    }
+
+    #[test]
+    fn map_literals_fold_keys_and_keep_order() {
+        let m = eval("{1: \"a\", \"k\": 2, 1.0: \"b\"}").unwrap();
+        assert_eq!(m.format_str(), "{1: \"b\", \"k\": 2}");
+        assert_eq!(eval("{1: \"a\"}[1.0]").unwrap().format_str(), "a");
+    }
+
+    #[test]
+    fn map_keys_must_be_strings_or_numbers() {
+        for src in ["{null: 1}", "{[1]: 1}", "{true: 1}", "{\"a\": 1}[{}]"] {
+            let e = eval(src).err().unwrap();
+            assert_eq!(e.code, 409, "{}", src);
+            assert!(e.msg.starts_with("Map keys must be a String or Number, got a "), "{}", src);
+        }
+    }
+
+    #[test]
+    fn compound_assignment_and_increments() {
+        let enviro = Rc::new(RefCell::new(Enviro::new()));
+        enviro.borrow_mut().define("x".to_string(), IntVal(5));
+        let run = |src: &str| eval_in(src, &enviro).unwrap().format_str();
+        assert_eq!(run("x += 2"), "7");
+        assert_eq!(run("x -= 1"), "6");
+        assert_eq!(run("x *= 3"), "18");
+        assert_eq!(run("x /= 4"), "4");
+        assert_eq!(run("x %= 3"), "1");
+        // postfix gives back the old value, prefix the new one
+        assert_eq!(run("x++"), "1");
+        assert_eq!(run("++x"), "3");
+        assert_eq!(run("x--"), "3");
+        assert_eq!(run("--x"), "1");
+        assert_eq!(run("x += 0.5"), "1.5");
+        assert_eq!(err_code("1 += 2"), 202);
+        assert_eq!(err_code("(1)++"), 202);
+    }
+
+    #[test]
+    fn compound_target_runs_once() {
+        let enviro = Rc::new(RefCell::new(Enviro::new()));
+        for (name, v) in [("i", IntVal(0)), ("xs", NullVal), ("m", NullVal)] {
+            enviro.borrow_mut().define(name.to_string(), v);
+        }
+        let run = |src: &str| eval_in(src, &enviro).unwrap().format_str();
+        run("xs = [10, 20, 30]");
+        assert_eq!(run("xs[i++] += 5"), "15");
+        assert_eq!(run("xs[i++]++"), "20");
+        assert_eq!(run("xs"), "[15, 21, 30]");
+        assert_eq!(run("i"), "2");
+        run("m = {\"a\": 1}");
+        assert_eq!(run("m[\"a\"] *= 10"), "10");
+        assert_eq!(eval_in("m[\"b\"] += 1", &enviro).err().unwrap().code, 407);
+    }
+}
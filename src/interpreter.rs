@@ -1,43 +1,423 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::collections::HashMap;
+use crate::builtins::define_builtins;
+use crate::callable::{Class, Function};
+use crate::diagnostic::Diagnostic;
 use crate::enviro::Enviro;
 use crate::expr::Expr;
 use crate::literals::*;
+use crate::scanner::Token;
 use crate::statement::Statement;
 
+// What a statement tells the enclosing loop / function to do once it finishes
+enum Flow {
+    Normal,
+    Break(Token),
+    Continue(Token),
+    Return(Token, LiteralVal),
+}
+
 pub struct Interpreter {
-    enviro: Enviro,
+    enviro: Rc<RefCell<Enviro>>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        let mut globals = Enviro::new();
+        define_builtins(&mut globals);
         Self {
-            enviro: Enviro::new(),
+            enviro: Rc::new(RefCell::new(globals)),
         }
     }
-    pub fn interpret(&mut self, pe: Expr) -> Result<LiteralVal, String> {
+    // Interpreter running on an existing scope (function calls)
+    pub fn with_enviro(enviro: Rc<RefCell<Enviro>>) -> Self {
+        Self { enviro }
+    }
+    pub fn interpret(&mut self, pe: Expr) -> Result<LiteralVal, Diagnostic> {
         pe.eval(&self.enviro)
     }
-    pub fn interpret_statement(&mut self, statement: Statement) -> Result<(), String> {
+    // Top level entry, a break/continue making it up here was never inside a loop
+    pub fn interpret_statement(&mut self, statement: Statement) -> Result<(), Diagnostic> {
+        match self.execute(&statement)? {
+            Flow::Normal => Ok(()),
+            Flow::Break(t) | Flow::Continue(t) => Err(Diagnostic::error(
+                410,
+                format!("-=({})=- is not inside a loop", t.lexeme),
+                t.span,
+            )),
+            Flow::Return(t, _) => Err(Diagnostic::error(
+                301,
+                format!("-=({})=- is not inside a function", t.lexeme),
+                t.span,
+            )),
+        }
+    }
+    // Runs a function body in the current scope, ret hands its value back out
+    pub fn execute_function_body(&mut self, body: &[Statement]) -> Result<LiteralVal, Diagnostic> {
+        for st in body {
+            match self.execute(st)? {
+                Flow::Normal => (),
+                Flow::Return(_, v) => return Ok(v),
+                Flow::Break(t) | Flow::Continue(t) => {
+                    return Err(Diagnostic::error(
+                        410,
+                        format!("-=({})=- is not inside a loop", t.lexeme),
+                        t.span,
+                    )
+                    .with_note("a loop outside the function does not count"))
+                }
+            }
+        }
+        Ok(LiteralVal::NullVal)
+    }
+    fn execute(&mut self, statement: &Statement) -> Result<Flow, Diagnostic> {
         match statement {
             Statement::Expression { expr } => {
                 expr.eval(&self.enviro)?;
             }
             Statement::Var { t, init } => {
                 let val = init.eval(&self.enviro)?;
-                self.enviro.define(t.lexeme, val);
+                self.enviro.borrow_mut().define(t.lexeme.clone(), val);
             }
             Statement::Print { expr } => {
                 let v = expr.eval(&self.enviro)?;
-                match v {
-                    LiteralVal::NumVal(ref x) => println!("{}", x),
-                    LiteralVal::StringVal(ref s) => println!("{}", s),
-                    LiteralVal::TrueVal => println!("true"),
-                    LiteralVal::FalseVal => println!("false"),
-                    LiteralVal::NullVal => println!("null"),
+                println!("{}", v.format_str());
+            }
+            Statement::Block { statements } => return self.execute_block(statements),
+            Statement::If {
+                cond,
+                then_b,
+                else_b,
+            } => {
+                if cond.eval(&self.enviro)?.is_truthy() {
+                    return self.execute(then_b);
+                } else if let Some(e) = else_b {
+                    return self.execute(e);
+                }
+            }
+            Statement::While { cond, body, step } => {
+                while cond.eval(&self.enviro)?.is_truthy() {
+                    match self.execute(body)? {
+                        Flow::Break(_) => break,
+                        Flow::Return(t, v) => return Ok(Flow::Return(t, v)),
+                        Flow::Normal | Flow::Continue(_) => (),
+                    }
+                    // step still runs after a continue so for loops move on
+                    if let Some(s) = step {
+                        s.eval(&self.enviro)?;
+                    }
                 }
             }
+            Statement::Break { t } => return Ok(Flow::Break(t.clone())),
+            Statement::Continue { t } => return Ok(Flow::Continue(t.clone())),
+            Statement::Function { name, params, body } => {
+                // captures the scope it was declared in, not the caller's
+                let fnc = Function {
+                    name: name.clone(),
+                    params: params.clone(),
+                    body: Rc::clone(body),
+                    closure: Rc::clone(&self.enviro),
+                    is_init: false,
+                };
+                self.enviro
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), LiteralVal::FncVal(Rc::new(fnc)));
+            }
+            Statement::Return { t, val } => {
+                let v = match val {
+                    Some(e) => e.eval(&self.enviro)?,
+                    None => LiteralVal::NullVal,
+                };
+                return Ok(Flow::Return(t.clone(), v));
+            }
+            Statement::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let sup = match superclass {
+                    Some(s) => match s.eval(&self.enviro)? {
+                        LiteralVal::ClassVal(c) => Some(c),
+                        other => {
+                            return Err(Diagnostic::error(
+                                411,
+                                format!(
+                                    "{} cannot inherit from -=({})=-, it is a {} not a Class",
+                                    name.lexeme,
+                                    other.format_str(),
+                                    other.as_literal_type()
+                                ),
+                                s.span(),
+                            )
+                            .with_label(name.span, "subclass declared here"))
+                        }
+                    },
+                    None => None,
+                };
+                // methods of a subclass close over a scope holding `super`
+                let outer = Rc::clone(&self.enviro);
+                if let Some(s) = &sup {
+                    let mut scope = Enviro::new_enclosed(Rc::clone(&outer));
+                    scope.define("super".to_string(), LiteralVal::ClassVal(Rc::clone(s)));
+                    self.enviro = Rc::new(RefCell::new(scope));
+                }
+                let mut fncs = HashMap::new();
+                for m in methods {
+                    if let Statement::Function { name, params, body } = m {
+                        let fnc = Function {
+                            name: name.clone(),
+                            params: params.clone(),
+                            body: Rc::clone(body),
+                            closure: Rc::clone(&self.enviro),
+                            is_init: name.lexeme == "init",
+                        };
+                        fncs.insert(name.lexeme.clone(), Rc::new(fnc));
+                    }
+                }
+                self.enviro = outer;
+                let class = Class {
+                    name: name.lexeme.clone(),
+                    superclass: sup,
+                    methods: fncs,
+                };
+                self.enviro
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), LiteralVal::ClassVal(Rc::new(class)));
+            }
+        }
+        Ok(Flow::Normal)
+    }
+    // Runs statements inside a fresh scope, outer scope is restored even on error
+    fn execute_block(&mut self, statements: &[Statement]) -> Result<Flow, Diagnostic> {
+        let outer = Rc::clone(&self.enviro);
+        self.enviro = Rc::new(RefCell::new(Enviro::new_enclosed(Rc::clone(&outer))));
+        let mut res = Ok(Flow::Normal);
+        for st in statements {
+            res = self.execute(st);
+            if !matches!(res, Ok(Flow::Normal)) {
+                break;
+            }
+        }
+        self.enviro = outer;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostics;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+
+    // Whole pipeline like main's run, the interpreter comes back so globals can be checked
+    fn run(src: &str) -> Result<Interpreter, Diagnostics> {
+        let tokens = Scanner::new(src).scan_tokens()?;
+        let statements = Parser::new(tokens).parse()?;
+        Resolver::new().resolve(&statements)?;
+        let mut intr = Interpreter::new();
+        for st in statements {
+            intr.interpret_statement(st)?;
+        }
+        Ok(intr)
+    }
+    fn global(intr: &Interpreter, name: &str) -> String {
+        intr.enviro.borrow().get(name).unwrap().format_str()
+    }
+
+    // First error of a program that is expected to fail
+    fn err(src: &str) -> Diagnostic {
+        run(src).err().unwrap().iter().next().unwrap().clone()
+    }
+
+    #[test]
+    fn dangling_else_binds_to_the_nearest_if() {
+        let src = "let a = \"none\"; if (true) if (false) a = \"inner\"; else a = \"else\";";
+        assert_eq!(global(&run(src).unwrap(), "a"), "else");
+        let src = "let a = \"none\"; if (false) if (true) a = \"inner\"; else a = \"else\";";
+        assert_eq!(global(&run(src).unwrap(), "a"), "none");
+    }
+
+    #[test]
+    fn if_truthiness_matches_bang() {
+        for v in ["0", "0.0", "\"\"", "null", "false", "1", "-2.5", "\"a\"", "true", "[]", "{}"] {
+            let src = format!(
+                "let a = 0; if ({0}) a = 1; else a = 2; let b = 0; if (!{0}) b = 2; else b = 1;",
+                v
+            );
+            let intr = run(&src).unwrap();
+            assert_eq!(global(&intr, "a"), global(&intr, "b"), "{}", v);
         }
-        Ok(())
+        let intr = run("let a = 0; if (0) a = 1; if (\"\") a = 2; if (null) a = 3;").unwrap();
+        assert_eq!(global(&intr, "a"), "0");
+    }
+
+    #[test]
+    fn while_and_for_loops() {
+        let intr = run("let n = 0; let i = 0; while (i < 5) { n = n + i; i = i + 1; }").unwrap();
+        assert_eq!(global(&intr, "n"), "10");
+        // for is a while with the step run after the body, its variable stays inside the loop
+        let intr = run("let n = 0; for (let i = 0; i < 4; i = i + 1) n = n * 10 + i;").unwrap();
+        assert_eq!(global(&intr, "n"), "123");
+        assert!(intr.enviro.borrow().get("i").is_none());
+        let intr = run("let n = 0; for (; n < 3;) n = n + 1;").unwrap();
+        assert_eq!(global(&intr, "n"), "3");
+    }
+
+    #[test]
+    fn continue_runs_the_step_and_break_exits() {
+        let src = "let s = \"\";
+            for (let i = 0; i < 10; i = i + 1) {
+                if (i % 2 == 0) continue;
+                if (i > 6) break;
+                s = s + \"${i}\";
+            }";
+        assert_eq!(global(&run(src).unwrap(), "s"), "135");
+        // break only leaves the innermost loop
+        let src = "let n = 0;
+            for (let i = 0; i < 3; i = i + 1) { while (true) { n = n + 1; break; } }";
+        assert_eq!(global(&run(src).unwrap(), "n"), "3");
+    }
+
+    #[test]
+    fn break_and_continue_outside_a_loop() {
+        assert_eq!(err("break;").code, 410);
+        assert_eq!(err("if (true) { continue; }").code, 410);
+        // a loop around the call does not count
+        let e = err("fnc f() { break; }\nwhile (true) { f(); }");
+        assert_eq!((e.code, e.span.line), (410, 1));
+        assert_eq!(e.notes, ["a loop outside the function does not count"]);
+        assert_eq!(err("fnc f() { continue; }\nfor (;;) f();").code, 410);
+    }
+
+    #[test]
+    fn undeclared_names_suggest_locals() {
+        let hint = vec!["did you mean `count`?".to_string()];
+        assert_eq!(err("fnc f() { let count = 0; cout = 1; }\nf();").notes, hint);
+        assert_eq!(err("fnc f() { let count = 0; print cout; }\nf();").notes, hint);
+        assert_eq!(err("let count = 0;\n{ cout = 1; }").notes, hint);
+        assert_eq!(err("zzz = 1;").notes, ["declare it first with let"]);
+    }
+
+    #[test]
+    fn calls_check_arity() {
+        let e = err("fnc f(a, b) { ret a; }\nf(1);");
+        assert_eq!(e.code, 404);
+        assert_eq!(e.msg, "Expected 2 arguments but got 1 when calling -=(f)=-");
+        assert_eq!((e.span.line, e.span.col), (2, 4));
+        assert_eq!(err("fnc f() {}\nf(1, 2);").code, 404);
+        assert_eq!(err("len();").code, 404);
+    }
+
+    #[test]
+    fn arguments_run_left_to_right() {
+        let src = "let s = \"\";
+            fnc note(x) { s = s + \"${x}\"; ret x; }
+            fnc f(a, b, c) { ret a * 100 + b * 10 + c; }
+            let r = f(note(1), note(2), note(3));";
+        let intr = run(src).unwrap();
+        assert_eq!(global(&intr, "s"), "123");
+        assert_eq!(global(&intr, "r"), "123");
+    }
+
+    #[test]
+    fn ret_unwinds_loops_and_blocks() {
+        let src = "fnc find(n) {
+                let i = 0;
+                while (true) {
+                    { for (let j = 0; j < 10; j = j + 1) { if (i * 10 + j == n) { ret [i, j]; } } }
+                    i = i + 1;
+                }
+            }
+            let a = find(42);
+            fnc none() { for (;;) { ret; } }
+            let b = none();";
+        let intr = run(src).unwrap();
+        assert_eq!(global(&intr, "a"), "[4, 2]");
+        assert_eq!(global(&intr, "b"), "null");
+        // the caller's scope is back after unwinding, a later global still lands in globals
+        let intr = run("fnc f() { { { ret 1; } } }\nlet x = f() + f();").unwrap();
+        assert_eq!(global(&intr, "x"), "2");
+    }
+
+    #[test]
+    fn instantiation_checks_init_arity() {
+        let e = err("cls P { init(x, y) { this.x = x; } }\nP(1);");
+        assert_eq!(e.code, 404);
+        assert_eq!(e.msg, "Expected 2 arguments but got 1 when making -=(P)=-");
+        // no init takes no arguments, an inherited init counts
+        assert_eq!(err("cls P {}\nP(1);").code, 404);
+        assert_eq!(err("cls P { init(x) {} }\ncls Q < P {}\nQ();").code, 404);
+        let intr = run("cls P { init(x) { this.x = x; } }\ncls Q < P {}\nlet q = Q(7).x;").unwrap();
+        assert_eq!(global(&intr, "q"), "7");
+    }
+
+    #[test]
+    fn fields_shadow_methods() {
+        let src = "cls A { f() { ret \"method\"; } }
+            let a = A();
+            let before = a.f();
+            a.f = \"field\";
+            let after = a.f;
+            let other = A().f();";
+        let intr = run(src).unwrap();
+        assert_eq!(global(&intr, "before"), "method");
+        assert_eq!(global(&intr, "after"), "field");
+        // only that instance's field, the class method is untouched
+        assert_eq!(global(&intr, "other"), "method");
+        assert_eq!(err("cls A {}\nA().nope;").code, 405);
+    }
+
+    #[test]
+    fn superclass_must_be_a_class() {
+        let e = err("let NotCls = 1;\ncls A < NotCls {}");
+        assert_eq!(e.code, 411);
+        assert_eq!(e.msg, "A cannot inherit from -=(1)=-, it is a Int not a Class");
+        assert_eq!((e.span.line, e.span.col), (2, 9));
+        assert_eq!(err("fnc f() {}\ncls A < f {}").code, 411);
+    }
+
+    #[test]
+    fn methods_are_found_up_the_superclass_chain() {
+        let src = "cls A { who() { ret \"A\"; } base() { ret \"base \" + this.who(); } }
+            cls B < A { who() { ret \"B\"; } }
+            cls C < B { both() { ret super.who() + this.base(); } }
+            let c = C();
+            let a = c.base();
+            let b = c.both();";
+        let intr = run(src).unwrap();
+        // base() lives two classes up, this.who() still finds the closest override
+        assert_eq!(global(&intr, "a"), "base B");
+        assert_eq!(global(&intr, "b"), "Bbase B");
+    }
+
+    #[test]
+    fn runaway_recursion_is_an_error() {
+        // the limit has to trip before the real stack runs out, so same stack main uses
+        std::thread::Builder::new()
+            .stack_size(crate::STACK_SIZE)
+            .spawn(runaway_recursion)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+    fn runaway_recursion() {
+        let f = "fnc f(n) { if (n == 0) ret 0; ret f(n - 1) + 1; }\n";
+        let errs = run(&format!("{}let a = f(100000);", f)).err().unwrap();
+        let err = errs.iter().next().unwrap();
+        assert_eq!(err.code, 415);
+        // points at the ) of the recursive call
+        assert_eq!(err.span.offset, f.find(") + 1").unwrap());
+
+        let errs = run("cls A { init() { A(); } }\nA();").err().unwrap();
+        assert_eq!(errs.iter().next().unwrap().code, 415);
+
+        // the depth unwinds with the error, deep but finite recursion still works after
+        let intr = run(&format!("{}let a = f(900);", f)).unwrap();
+        assert_eq!(global(&intr, "a"), "900");
     }
 }
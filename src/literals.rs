@@ -1,33 +1,174 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::rc::Rc;
+use std::thread::LocalKey;
+use crate::callable::{Class, Function, Instance, NativeFnc};
+use crate::diagnostic::Diagnostic;
 use crate::literals::LiteralVal::*;
 use crate::scanner;
 use crate::scanner::{Token, TokenType};
 
 // Literal values class (enum because Rust)
-#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Debug)]
 pub enum LiteralVal {
-    NumVal(f32),
+    // floats, integer literals make IntVal
+    NumVal(f64),
+    IntVal(i64),
     StringVal(String),
     TrueVal,
     FalseVal,
     NullVal,
+    FncVal(Rc<Function>),
+    ClassVal(Rc<Class>),
+    InstanceVal(Rc<RefCell<Instance>>),
+    // shared, so every variable holding the list sees pushes/sets
+    ListVal(Rc<RefCell<Vec<LiteralVal>>>),
+    MapVal(Rc<RefCell<MidasMap>>),
+    NativeVal(Rc<NativeFnc>),
 }
 
-// Helper for token_fmt, just tries to unwrap value in Result type
-fn unwrap_as_f32(literal: Option<scanner::LiteralVal>) -> f32 {
+// Map keys, only strings and numbers can be used
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Str(String),
+    Int(i64),
+    // float stored as bits so it can be hashed
+    Num(u64),
+}
+
+impl MapKey {
+    // Whole floats fold into the Int key so m[1] and m[1.0] are the same entry (1 == 1.0)
+    pub fn from_literal(v: &LiteralVal) -> Option<MapKey> {
+        match v {
+            IntVal(i) => Some(MapKey::Int(*i)),
+            NumVal(x) if x.fract() == 0.0 && x.abs() < 9.2e18 => Some(MapKey::Int(*x as i64)),
+            NumVal(x) => Some(MapKey::Num(x.to_bits())),
+            StringVal(s) => Some(MapKey::Str(s.clone())),
+            _ => None,
+        }
+    }
+    pub fn to_literal(&self) -> LiteralVal {
+        match self {
+            MapKey::Str(s) => StringVal(s.clone()),
+            MapKey::Int(i) => IntVal(*i),
+            MapKey::Num(b) => NumVal(f64::from_bits(*b)),
+        }
+    }
+}
+
+// Key/value map that remembers insertion order (printing and keys() come out in that order)
+#[derive(Clone, Debug, Default)]
+pub struct MidasMap {
+    index: HashMap<MapKey, usize>,
+    entries: Vec<(MapKey, LiteralVal)>,
+}
+
+impl MidasMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, key: &MapKey) -> Option<&LiteralVal> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+    // Overwriting a key keeps its original spot in the order
+    pub fn insert(&mut self, key: MapKey, val: LiteralVal) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = val,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, val));
+            }
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn entries(&self) -> &[(MapKey, LiteralVal)] {
+        &self.entries
+    }
+}
+
+// Lists/maps being printed or compared further up the stack. They are shared so one can hold
+// itself (xs[0] = xs), meeting it again means a cycle instead of recursing forever
+thread_local! {
+    static PRINTING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+    static COMPARING: RefCell<HashSet<(usize, usize)>> = RefCell::new(HashSet::new());
+}
+
+fn addr<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as usize
+}
+fn pair<T>(x: &Rc<T>, y: &Rc<T>) -> (usize, usize) {
+    (addr(x), addr(y))
+}
+
+// Runs f with key marked as visiting, None if it already is (a cycle)
+fn visit<K: Eq + Hash + Copy, T>(
+    visiting: &'static LocalKey<RefCell<HashSet<K>>>,
+    key: K,
+    f: impl FnOnce() -> T,
+) -> Option<T> {
+    if !visiting.with(|v| v.borrow_mut().insert(key)) {
+        return None;
+    }
+    let res = f();
+    visiting.with(|v| v.borrow_mut().remove(&key));
+    Some(res)
+}
+
+// Derived equality except numbers compare by value across types (1 == 1.0)
+impl PartialEq for LiteralVal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (NumVal(x), NumVal(y)) => x == y,
+            (IntVal(x), IntVal(y)) => x == y,
+            (IntVal(i), NumVal(x)) | (NumVal(x), IntVal(i)) => *i as f64 == *x,
+            (StringVal(x), StringVal(y)) => x == y,
+            (TrueVal, TrueVal) | (FalseVal, FalseVal) | (NullVal, NullVal) => true,
+            (FncVal(x), FncVal(y)) => x == y,
+            (ClassVal(x), ClassVal(y)) => x == y,
+            (InstanceVal(x), InstanceVal(y)) => x == y,
+            (ListVal(x), ListVal(y)) => {
+                Rc::ptr_eq(x, y) || visit(&COMPARING, pair(x, y), || x == y).unwrap_or(true)
+            }
+            (MapVal(x), MapVal(y)) => {
+                Rc::ptr_eq(x, y) || visit(&COMPARING, pair(x, y), || x == y).unwrap_or(true)
+            }
+            (NativeVal(x), NativeVal(y)) => x == y,
+            _ => false,
+        }
+    }
+}
+
+// Same keys with equal values, order does not matter
+impl PartialEq for MidasMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.entries.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+// Helper for token_fmt, None when the token is not carrying a number
+fn unwrap_as_num(literal: Option<scanner::LiteralVal>) -> Option<LiteralVal> {
     match literal {
-        Some(scanner::LiteralVal::IntVal(i)) => i as f32,
-        Some(scanner::LiteralVal::FVal(i)) => i as f32,
-        _ => panic!("Could not unwrap as f32"),
+        Some(scanner::LiteralVal::IntVal(i)) => Some(IntVal(i)),
+        Some(scanner::LiteralVal::FVal(x)) => Some(NumVal(x)),
+        _ => None,
     }
 }
-// Helper for token_fmt, just tries to unwrap value in Result type
-fn unwrap_as_str(literal: Option<scanner::LiteralVal>) -> String {
+// Helper for token_fmt, None when the token is not carrying text
+fn unwrap_as_str(literal: Option<scanner::LiteralVal>) -> Option<String> {
     match literal {
-        Some(scanner::LiteralVal::StringVal(s)) => s.clone(),
-        Some(scanner::LiteralVal::IdentifierVal(s)) => s.clone(),
-        _ => panic!("Could not unwrap as String"),
+        Some(scanner::LiteralVal::StringVal(s)) => Some(s),
+        Some(scanner::LiteralVal::IdentifierVal(s)) => Some(s),
+        _ => None,
     }
 }
 
@@ -35,47 +176,130 @@ impl LiteralVal {
     // Formats the literal value as a string
     pub fn format_str(&self) -> String {
         match self {
+            // very big/small floats print the way they are written (6.02e23)
+            LiteralVal::NumVal(v) if *v != 0.0 && (v.abs() >= 1e16 || v.abs() < 1e-5) => {
+                format!("{:e}", v)
+            }
+            // whole floats keep a .0 so they do not read as ints
+            LiteralVal::NumVal(v) if v.fract() == 0.0 && v.is_finite() => format!("{:.1}", v),
             LiteralVal::NumVal(v) => v.to_string(),
+            LiteralVal::IntVal(i) => i.to_string(),
             LiteralVal::StringVal(s) => (&s).to_string(),
             LiteralVal::TrueVal => "true".to_string(),
             LiteralVal::FalseVal => "false".to_string(),
             LiteralVal::NullVal => "null".to_string(),
+            LiteralVal::FncVal(f) => format!("<fnc {}>", f.name.lexeme),
+            LiteralVal::ClassVal(c) => format!("<cls {}>", c.name),
+            LiteralVal::InstanceVal(i) => format!("<{} instance>", i.borrow().class.name),
+            LiteralVal::ListVal(_) | LiteralVal::MapVal(_) => {
+                let mut out = String::new();
+                self.write_nested(&mut out);
+                out
+            }
+            LiteralVal::NativeVal(n) => format!("<native fnc {}>", n.name),
+        }
+    }
+    // Like format_str but strings keep their quotes (items inside a list). It all goes into
+    // one buffer so deeply nested lists are not copied again at every level
+    fn write_nested(&self, out: &mut String) {
+        match self {
+            LiteralVal::StringVal(s) => {
+                out.push('"');
+                out.push_str(s);
+                out.push('"');
+            }
+            LiteralVal::ListVal(l) => {
+                let done = visit(&PRINTING, addr(l), || {
+                    out.push('[');
+                    for (i, v) in l.borrow().iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        v.write_nested(out);
+                    }
+                    out.push(']');
+                });
+                if done.is_none() {
+                    out.push_str("[...]");
+                }
+            }
+            LiteralVal::MapVal(m) => {
+                let done = visit(&PRINTING, addr(m), || {
+                    out.push('{');
+                    for (i, (k, v)) in m.borrow().entries().iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        k.to_literal().write_nested(out);
+                        out.push_str(": ");
+                        v.write_nested(out);
+                    }
+                    out.push('}');
+                });
+                if done.is_none() {
+                    out.push_str("{...}");
+                }
+            }
+            v => out.push_str(&v.format_str()),
         }
     }
 
     pub fn as_literal_type(&self) -> String {
         match self {
-            LiteralVal::NumVal(_) => "Number".to_string(),
+            LiteralVal::NumVal(_) => "Float".to_string(),
+            LiteralVal::IntVal(_) => "Int".to_string(),
             LiteralVal::StringVal(_) => "String".to_string(),
             LiteralVal::TrueVal => "Boolean".to_string(),
             LiteralVal::FalseVal => "Boolean".to_string(),
-            LiteralVal::NullVal => "Boolean".to_string(),
+            LiteralVal::NullVal => "Null".to_string(),
+            LiteralVal::FncVal(_) => "Function".to_string(),
+            LiteralVal::ClassVal(_) => "Class".to_string(),
+            LiteralVal::InstanceVal(_) => "Instance".to_string(),
+            LiteralVal::ListVal(_) => "List".to_string(),
+            LiteralVal::MapVal(_) => "Map".to_string(),
+            LiteralVal::NativeVal(_) => "Function".to_string(),
         }
     }
-    // Formats the Token input into a Literal value
-    pub fn token_fmt(token: Token) -> Self {
-        match token.token_type {
-            TokenType::Number => Self::NumVal(unwrap_as_f32(token.literal)),
-            TokenType::StringLit => Self::StringVal(unwrap_as_str(token.literal)),
-            TokenType::True => Self::TrueVal,
-            TokenType::False => Self::FalseVal,
-            TokenType::Null => Self::NullVal,
-            _ => panic!("Failed to format token to literal"),
-        }
+    // Formats the Token input into a Literal value, errors if the token does not hold one
+    pub fn token_fmt(token: Token) -> Result<Self, Diagnostic> {
+        let span = token.span;
+        let val = match token.token_type {
+            TokenType::Number => unwrap_as_num(token.literal),
+            TokenType::StringLit | TokenType::StringInterp => {
+                unwrap_as_str(token.literal).map(Self::StringVal)
+            }
+            TokenType::True => Some(Self::TrueVal),
+            TokenType::False => Some(Self::FalseVal),
+            TokenType::Null => Some(Self::NullVal),
+            _ => None,
+        };
+        val.ok_or_else(|| {
+            Diagnostic::error(
+                206,
+                format!("-=({})=- is not a literal value", token.lexeme),
+                span,
+            )
+        })
     }
-    // Truth evaluators for strings/nums (ex 0 or "" is not truthy)
-    // TODO: Make lists not truthy? How am I gonna implement those haha
+    // Truth evaluators for strings/nums/lists (ex 0, "" or [] is not truthy)
     pub fn is_falsy(&self) -> LiteralVal {
         match self {
             NumVal(x) => {
-                if x.clone() == 0.0 as f32 {
+                if *x == 0.0 {
+                    TrueVal
+                } else {
+                    FalseVal
+                }
+            }
+            IntVal(i) => {
+                if *i == 0 {
                     TrueVal
                 } else {
                     FalseVal
                 }
             }
             StringVal(s) => {
-                if s.len() == 0 {
+                if s.is_empty() {
                     TrueVal
                 } else {
                     FalseVal
@@ -84,8 +308,35 @@ impl LiteralVal {
             TrueVal => FalseVal,
             FalseVal => TrueVal,
             NullVal => TrueVal,
+            ListVal(l) => {
+                if l.borrow().is_empty() {
+                    TrueVal
+                } else {
+                    FalseVal
+                }
+            }
+            MapVal(m) => {
+                if m.borrow().is_empty() {
+                    TrueVal
+                } else {
+                    FalseVal
+                }
+            }
+            FncVal(_) | NativeVal(_) | ClassVal(_) | InstanceVal(_) => FalseVal,
         }
     }
+    // Either number type as a float, for mixed arithmetic and comparisons
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            NumVal(x) => Some(*x),
+            IntVal(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+    // Same rules as is_falsy but as a plain bool for control flow (if, while...)
+    pub fn is_truthy(&self) -> bool {
+        self.is_falsy() == FalseVal
+    }
     // Very simple bool truthy check to convert as a LiteralVal for booleans
     pub fn is_boolean_truthy(b: bool) -> Self {
         if b {
@@ -95,3 +346,84 @@ impl LiteralVal {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: Vec<LiteralVal>) -> Rc<RefCell<Vec<LiteralVal>>> {
+        Rc::new(RefCell::new(items))
+    }
+
+    #[test]
+    fn self_holding_list_prints_and_compares() {
+        let xs = list(vec![IntVal(1)]);
+        xs.borrow_mut()[0] = ListVal(Rc::clone(&xs));
+        assert_eq!(ListVal(Rc::clone(&xs)).format_str(), "[[...]]");
+        assert!(ListVal(Rc::clone(&xs)) == ListVal(Rc::clone(&xs)));
+        // two separate cycles with the same shape
+        let ys = list(vec![IntVal(1)]);
+        ys.borrow_mut()[0] = ListVal(Rc::clone(&ys));
+        assert!(ListVal(Rc::clone(&xs)) == ListVal(Rc::clone(&ys)));
+        // the same list twice is not a cycle
+        let s = ListVal(list(vec![IntVal(2)]));
+        assert_eq!(ListVal(list(vec![s.clone(), s])).format_str(), "[[2], [2]]");
+    }
+
+    #[test]
+    fn map_overwrite_keeps_its_spot() {
+        let mut m = MidasMap::new();
+        for (k, v) in [("a", 1), ("b", 2), ("a", 3)] {
+            m.insert(MapKey::Str(k.to_string()), IntVal(v));
+        }
+        assert_eq!(MapVal(Rc::new(RefCell::new(m))).format_str(), "{\"a\": 3, \"b\": 2}");
+    }
+
+    #[test]
+    fn whole_floats_share_the_int_key() {
+        let key = |v: LiteralVal| MapKey::from_literal(&v);
+        assert_eq!(key(NumVal(1.0)), Some(MapKey::Int(1)));
+        assert_eq!(key(NumVal(-0.0)), Some(MapKey::Int(0)));
+        assert_eq!(key(NumVal(1.5)), Some(MapKey::Num(1.5f64.to_bits())));
+        assert!(matches!(MapKey::Num(1.5f64.to_bits()).to_literal(), NumVal(x) if x == 1.5));
+        assert_eq!(key(NullVal), None);
+        assert_eq!(key(ListVal(list(vec![]))), None);
+    }
+
+    #[test]
+    fn deep_nesting_prints_and_compares_in_linear_time() {
+        // deep recursion, so the same stack main gives the interpreter
+        std::thread::Builder::new()
+            .stack_size(crate::STACK_SIZE)
+            .spawn(deep_nesting)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+    fn deep_nesting() {
+        let chain = || (0..80_000).fold(IntVal(0), |l, i| ListVal(list(vec![IntVal(i), l])));
+        let (a, b) = (chain(), chain());
+        // this used to rescan every enclosing list at each level, 30+ seconds
+        let start = std::time::Instant::now();
+        assert!(a == b);
+        assert!(a.format_str().starts_with("[79999, [79998, "));
+        assert!(start.elapsed().as_secs() < 5, "took {:?}", start.elapsed());
+    }
+
+    #[test]
+    fn large_and_small_floats_print_with_exponent() {
+        assert_eq!(NumVal(6.02e23).format_str(), "6.02e23");
+        assert_eq!(NumVal(1.5e-7).format_str(), "1.5e-7");
+        assert_eq!(NumVal(1000.0).format_str(), "1000.0");
+        assert_eq!(NumVal(0.0).format_str(), "0.0");
+    }
+
+    #[test]
+    fn self_holding_map_prints_and_compares() {
+        let m = Rc::new(RefCell::new(MidasMap::new()));
+        m.borrow_mut().insert(MapKey::Str("k".to_string()), MapVal(Rc::clone(&m)));
+        assert_eq!(MapVal(Rc::clone(&m)).format_str(), "{\"k\": {...}}");
+        assert!(MapVal(Rc::clone(&m)) == MapVal(Rc::clone(&m)));
+        assert_eq!(ListVal(list(vec![MapVal(m)])).format_str(), "[{\"k\": {...}}]");
+    }
+}
@@ -1,27 +1,36 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+mod builtins;
+mod callable;
+mod diagnostic;
 mod expr;
 mod interpreter;
 mod enviro;
 mod literals;
 mod parser;
+mod resolver;
 mod scanner;
 mod statement;
+use crate::diagnostic::Diagnostics;
 use crate::interpreter::*;
 use crate::parser::*;
+use crate::resolver::Resolver;
 use crate::scanner::*;
 use std::env;
 use std::fs;
 use std::io::{self, BufRead, Write};
 use std::process::exit;
+use std::thread;
 
 // Terminal view, takes in user input
 fn run_prompt() -> Result<(), String> {
     // NOTE: this might need to be put in loop, will see!
     let mut intr: Interpreter = Interpreter::new();
     let mut buff = String::new();
+    // everything typed so far, spans from older lines (a fnc called later) point into it
+    let mut history = String::new();
     loop {
-        print!("{}", "(/•ิ_•ิ)/ → ");
+        print!("(/•ิ_•ิ)/ → ");
         // check if can properly display
         match io::stdout().flush() {
             Ok(_) => (),
@@ -36,46 +45,86 @@ fn run_prompt() -> Result<(), String> {
         }
         // quit loop
         if buff.trim() == "exit" {
-            println!("{}", "Stay gold, Ponyboy...");
+            println!("Stay gold, Ponyboy...");
             break Ok(());
         }
         // run user input
-        match run(&mut intr, &buff) {
-            Ok(_) => (),
-            Err(e) => println!("{}", e),
+        if let Err(e) = run_line(&mut intr, &mut history, &buff) {
+            print!("{}", e);
         }
         buff.clear();
     }
 }
-// Reads file contents and runs
+// Adds the line to the history and runs just that line, errors come back rendered
+fn run_line(intr: &mut Interpreter, history: &mut String, line: &str) -> Result<(), String> {
+    let offset = history.len();
+    let line_num = history.matches('\n').count() + 1;
+    history.push_str(line);
+    if !line.ends_with('\n') {
+        history.push('\n');
+    }
+    run(intr, Scanner::new_at(history, offset, line_num))
+        .map_err(|e| e.render(history, "<prompt>"))
+}
+// Reads file contents and runs, errors get printed against the source
 fn run_file(path: &str) -> Result<(), String> {
     let mut intr = Interpreter::new();
-    match fs::read_to_string(path) {
-        Ok(c) => return run(&mut intr, &c),
-        Err(e) => return Err(e.to_string()),
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    if let Err(e) = run(&mut intr, Scanner::new(&contents)) {
+        eprint!("{}", e.render(&contents, path));
+        exit(65)
     }
+    Ok(())
 }
 // Run and get tokens
-fn run(intr: &mut Interpreter, contents: &str) -> Result<(), String> {
-    let mut s = Scanner::new(contents);
-    let tokens = s.scan_tokens()?;
+fn run(intr: &mut Interpreter, mut scanner: Scanner) -> Result<(), Diagnostics> {
+    let tokens = scanner.scan_tokens()?;
     let mut p = Parser::new(tokens);
     let stmnts = p.parse()?;
+    Resolver::new().resolve(&stmnts)?;
     for st in stmnts {
         intr.interpret_statement(st)?;
     }
     Ok(())
 }
 
+// Stack for the thread everything runs on, parsing and evaluating recurse per nesting level
+// (a debug build call level can take tens of KB, MAX_CALL_DEPTH of them has to fit)
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
 fn main() -> Result<(), String> {
     let args: Vec<String> = env::args().collect();
-    match args.len() {
-        // 2 args = script file, 1 = interactive mode, else exit
-        1 => run_prompt(),
-        2 => run_file(&args[1]),
-        _ => {
-            println!("Usage: midas_lang[script]");
-            exit(64)
-        }
+    let worker = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || match args.len() {
+            // 2 args = script file, 1 = interactive mode, else exit
+            1 => run_prompt(),
+            2 => run_file(&args[1]),
+            _ => {
+                println!("Usage: midas_lang[script]");
+                exit(64)
+            }
+        })
+        .map_err(|e| e.to_string())?;
+    worker.join().map_err(|_| "Interpreter thread crashed".to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_errors_render_against_earlier_lines() {
+        let mut intr = Interpreter::new();
+        let mut history = String::new();
+        run_line(&mut intr, &mut history, "fnc f() { ret 1 + \"abc\"; }\n").unwrap();
+        // multi-byte text on the failing line used to be sliced at the old line's offsets
+        let line = "let s = \"ééééééééééé\"; f();\n";
+        let err = run_line(&mut intr, &mut history, line).unwrap_err();
+        assert!(err.contains("--> <prompt>:1:17"), "{}", err);
+        assert!(err.contains("1 | fnc f() { ret 1 + \"abc\"; }"), "{}", err);
+        // errors in the new line get its own line number
+        let err = run_line(&mut intr, &mut history, "print nope;").unwrap_err();
+        assert!(err.contains("3 | print nope;"), "{}", err);
     }
 }
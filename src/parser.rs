@@ -1,88 +1,438 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
-use crate::expr::{Expr, Expr::*};
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::expr::{Expr, Expr::*, Update};
 use crate::literals::LiteralVal;
-use crate::scanner::{Token, TokenType, TokenType::*};
+use crate::scanner::{foreign_kw, Span, Token, TokenType, TokenType::*};
 use crate::statement::Statement;
+use std::cell::Cell;
+use std::rc::Rc;
+
+// How deep statements/expressions can nest before parsing gives up (keeps the stack safe)
+const MAX_DEPTH: usize = 128;
+// Errors reported before giving up, later ones are mostly fallout from the first few
+const MAX_ERRORS: usize = 20;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    depth: usize,
+    // blocks currently open, synchronize leaves their } for block() to close
+    blocks: usize,
+    errs: Diagnostics,
 }
 
 impl Parser {
     // Construct the parser struct
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            depth: 0,
+            blocks: 0,
+            errs: Diagnostics::new(),
+        }
     }
-    pub fn parse(&mut self) -> Result<Vec<Statement>, String> {
+    pub fn parse(&mut self) -> Result<Vec<Statement>, Diagnostics> {
         let mut statements: Vec<Statement> = Vec::new();
-        let mut errs: Vec<String> = Vec::new();
         while !self.is_at_end() {
-            let statement = self.declaration();
-            match statement {
+            match self.declaration() {
                 Ok(st) => statements.push(st),
-                Err(e) => {
-                    errs.push(e);
-                    self.synchronize();
-                }
+                Err(e) => self.recover(e),
             }
         }
-        if errs.len() == 0 {
+        if self.errs.is_empty() {
             Ok(statements)
         } else {
-            Err(errs.join("\n =+> "))
+            Err(std::mem::take(&mut self.errs))
+        }
+    }
+
+    // Records the error and skips to the next statement, once there are too many it jumps
+    // to the end so everything still being parsed unwinds without piling on more
+    fn recover(&mut self, e: Diagnostic) {
+        if self.errs.len() >= MAX_ERRORS {
+            return;
         }
+        self.errs.push(e);
+        if self.errs.len() == MAX_ERRORS {
+            self.errs.push(
+                Diagnostic::error(207, "Too many errors, stopping here", self.peek().span)
+                    .with_note("fix the errors above and try again"),
+            );
+            self.current = self.tokens.len();
+            return;
+        }
+        self.synchronize();
     }
 
-    fn declaration(&mut self) -> Result<Statement, String> {
+    fn declaration(&mut self) -> Result<Statement, Diagnostic> {
+        self.foreign_keyword()?;
         if self.matching(Var) {
-            return self.var_declaration();
+            self.var_declaration()
+        } else if self.matching(Fun) {
+            self.function()
+        } else if self.matching(Class) {
+            self.class_declaration()
         } else {
-            return self.statement();
+            self.statement()
+        }
+    }
+
+    // `var x = 1;`, `return x;`, `class A {`... an identifier straight after another one can
+    // not be valid, if the first is another language's keyword say which one we use instead
+    fn foreign_keyword(&self) -> Result<(), Diagnostic> {
+        let t = self.peek();
+        match self.foreign_kw_at() {
+            Some(w) => Err(Diagnostic::error(
+                208,
+                format!("-=({})=- is not a keyword in midas", t.lexeme),
+                t.span,
+            )
+            .with_note(format!("did you mean `{}`?", w))),
+            None => Ok(()),
+        }
+    }
+    // Our keyword for the foreign one starting a statement at current, if there is one
+    fn foreign_kw_at(&self) -> Option<&'static str> {
+        let t = self.tokens.get(self.current)?;
+        let next = self.tokens.get(self.current + 1).map(|n| n.token_type);
+        match (t.token_type, next) {
+            (Identifier, Some(Identifier | Number | StringLit | StringInterp)) => {
+                foreign_kw(&t.lexeme)
+            }
+            _ => None,
         }
     }
 
-    fn var_declaration(&mut self) -> Result<Statement, String> {
+    fn var_declaration(&mut self) -> Result<Statement, Diagnostic> {
         let tk = self.consume(Identifier, "Expected variable name")?;
 
-        let initi;
-        if self.matching(Equal) {
-            initi = self.expression()?;
+        let initi = if self.matching(Equal) {
+            self.expression()?
         } else {
-            initi = Literal { val: LiteralVal::NullVal };
-        }
+            Literal {
+                val: LiteralVal::NullVal,
+                span: tk.span,
+            }
+        };
 
         self.consume(Semicolon, "Expecting -=(';')=- at end")?;
         Ok(Statement::Var { t: tk, init: initi  })
     }
 
-    fn statement(&mut self) -> Result<Statement, String> {
+    // cls Name < Parent { method(a) { ... } ... }, methods skip the fnc keyword
+    fn class_declaration(&mut self) -> Result<Statement, Diagnostic> {
+        let name = self.consume(Identifier, "Expected class name after 'cls'")?;
+        let superclass = if self.matching(Less) {
+            let sup = self.consume(Identifier, "Expected superclass name after '<'")?;
+            Some(Variable {
+                name: sup,
+                slot: Cell::new(None),
+            })
+        } else {
+            None
+        };
+        self.consume(LBrace, "Expecting -=('{')=- before class body")?;
+        let mut methods: Vec<Statement> = Vec::new();
+        while !self.check(RBrace) && !self.is_at_end() {
+            methods.push(self.function()?);
+        }
+        self.consume(RBrace, "Expecting -=('}')=- after class body")?;
+        Ok(Statement::Class {
+            name,
+            superclass,
+            methods,
+        })
+    }
+
+    // fnc name(a, b) { ... }
+    fn function(&mut self) -> Result<Statement, Diagnostic> {
+        let name = self.consume(Identifier, "Expected function name")?;
+        self.consume(LParen, "Expecting -=('(')=- after function name")?;
+        let mut params: Vec<Token> = Vec::new();
+        if !self.check(RParen) {
+            loop {
+                if params.len() >= 255 {
+                    return Err(Diagnostic::error(
+                        203,
+                        "Cannot have more than 255 parameters",
+                        self.peek().span,
+                    ));
+                }
+                params.push(self.consume(Identifier, "Expected parameter name")?);
+                if !self.matching(Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(RParen, "Expecting -=(')')=- after parameters")?;
+        self.consume(LBrace, "Expecting -=('{')=- before function body")?;
+        let body = self.block()?;
+        Ok(Statement::Function {
+            name,
+            params,
+            body: Rc::new(body),
+        })
+    }
+
+    fn statement(&mut self) -> Result<Statement, Diagnostic> {
         if self.matching(Print) {
-            return self.print_statement();
+            self.print_statement()
+        } else if self.matching(If) {
+            self.if_statement()
+        } else if self.matching(While) {
+            self.while_statement()
+        } else if self.matching(For) {
+            self.for_statement()
+        } else if self.matchings(&[Break, Continue]) {
+            self.loop_jump_statement()
+        } else if self.matching(Return) {
+            self.return_statement()
+        } else if self.check(LBrace) && !self.starts_map() {
+            self.advance();
+            Ok(Statement::Block {
+                statements: self.block()?,
+            })
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    // A { at the start of a statement is a block unless it opens with `key:`. The key can be
+    // any expression ({ k: 1 }, { "a${x}": 1 }) so it is parsed ahead and then rewound
+    fn starts_map(&mut self) -> bool {
+        let key = self.tokens.get(self.current + 1).map(|t| t.token_type);
+        if !matches!(key, Some(StringLit | StringInterp | Number | Identifier)) {
+            return false;
+        }
+        let start = self.current;
+        self.advance();
+        let is_map = self.expression().is_ok() && self.check(Colon);
+        self.current = start;
+        is_map
+    }
+
+    // Else binds to the nearest if since we grab it right after the then branch
+    fn if_statement(&mut self) -> Result<Statement, Diagnostic> {
+        self.consume(LParen, "Expecting -=('(')=- after 'if'")?;
+        let cond = self.expression()?;
+        self.consume(RParen, "Expecting -=(')')=- after if condition")?;
+        let then_b = self.nested(Self::statement)?;
+        let else_b = if self.matching(Else) {
+            Some(Box::from(self.nested(Self::statement)?))
+        } else {
+            None
+        };
+        Ok(Statement::If {
+            cond,
+            then_b: Box::from(then_b),
+            else_b,
+        })
+    }
+
+    fn while_statement(&mut self) -> Result<Statement, Diagnostic> {
+        self.consume(LParen, "Expecting -=('(')=- after 'while'")?;
+        let cond = self.expression()?;
+        self.consume(RParen, "Expecting -=(')')=- after while condition")?;
+        let body = self.nested(Self::statement)?;
+        Ok(Statement::While {
+            cond,
+            body: Box::from(body),
+            step: None,
+        })
+    }
+
+    // Desugars for (init; cond; step) body into { init; while (cond) body + step }
+    fn for_statement(&mut self) -> Result<Statement, Diagnostic> {
+        self.consume(LParen, "Expecting -=('(')=- after 'for'")?;
+        let init = if self.matching(Semicolon) {
+            None
+        } else if self.matching(Var) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let cond = if self.check(Semicolon) {
+            Literal {
+                val: LiteralVal::TrueVal,
+                span: self.peek().span,
+            }
+        } else {
+            self.expression()?
+        };
+        self.consume(Semicolon, "Expecting -=(';')=- after for loop condition")?;
+
+        let step = if self.check(RParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(RParen, "Expecting -=(')')=- after for clauses")?;
+
+        let body = self.nested(Self::statement)?;
+        let looped = Statement::While {
+            cond,
+            body: Box::from(body),
+            step,
+        };
+        match init {
+            Some(i) => Ok(Statement::Block {
+                statements: vec![i, looped],
+            }),
+            None => Ok(looped),
+        }
+    }
+
+    // break / continue, whether we are in a loop gets checked when run
+    fn loop_jump_statement(&mut self) -> Result<Statement, Diagnostic> {
+        let t = self.previous();
+        self.consume(Semicolon, "Expecting -=(';')=- at end")?;
+        match t.token_type {
+            Break => Ok(Statement::Break { t }),
+            _ => Ok(Statement::Continue { t }),
+        }
+    }
+
+    fn return_statement(&mut self) -> Result<Statement, Diagnostic> {
+        let t = self.previous();
+        let val = if self.check(Semicolon) {
+            None
         } else {
-            return self.expression_statement();
+            Some(self.expression()?)
+        };
+        self.consume(Semicolon, "Expecting -=(';')=- after return value")?;
+        Ok(Statement::Return { t, val })
+    }
+
+    // Parses declarations up to the closing brace, opening brace already consumed
+    // A bad statement inside is reported and skipped, the rest of the block still parses
+    fn block(&mut self) -> Result<Vec<Statement>, Diagnostic> {
+        let mut statements: Vec<Statement> = Vec::new();
+        self.blocks += 1;
+        while !self.check(RBrace) && !self.is_at_end() {
+            match self.nested(Self::declaration) {
+                Ok(st) => statements.push(st),
+                Err(e) => self.recover(e),
+            }
         }
+        self.blocks -= 1;
+        self.consume(RBrace, "Expecting -=('}')=- after block")?;
+        Ok(statements)
     }
 
-    fn print_statement(&mut self) -> Result<Statement, String> {
+    fn print_statement(&mut self) -> Result<Statement, Diagnostic> {
         let v: Expr = self.expression()?;
         self.consume(Semicolon, "Expecting -=(';')=- at end")?;
         Ok(Statement::Print { expr: v })
     }
 
-    fn expression_statement(&mut self) -> Result<Statement, String> {
+    fn expression_statement(&mut self) -> Result<Statement, Diagnostic> {
         let ex: Expr = self.expression()?;
         self.consume(Semicolon, "Expecting -=(';')=- at end")?;
         Ok(Statement::Expression { expr: ex })
     }
 
-    // Expands to equality rule
-    pub fn expression(&mut self) -> Result<Expr, String> {
-        self.equality()
+    // Expands to assignment rule
+    pub fn expression(&mut self) -> Result<Expr, Diagnostic> {
+        self.nested(Self::assignment)
+    }
+    // Right associative, so recurse on the right side (a = b = 3 -> a = (b = 3))
+    fn assignment(&mut self) -> Result<Expr, Diagnostic> {
+        let expr = self.or()?;
+        if self.matchings(&[Equal, PlusEqual, MinusEqual, StarEqual, SlashEqual, PercentEqual]) {
+            let equals = self.previous();
+            let val = self.assignment()?;
+            // a += v stores a + v, the op keeps the += lexeme/span for errors
+            let op = match equals.token_type {
+                PlusEqual => Some(Plus),
+                MinusEqual => Some(Minus),
+                StarEqual => Some(Star),
+                SlashEqual => Some(Slash),
+                PercentEqual => Some(Percent),
+                _ => None,
+            };
+            let update = op.map(|token_type| Update {
+                op: Token {
+                    token_type,
+                    ..equals.clone()
+                },
+                postfix: false,
+            });
+            return assign_to(expr, val, update, &equals);
+        }
+        Ok(expr)
+    }
+    // or binds looser than and, so a or b and c -> a or (b and c)
+    fn or(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.and()?;
+        while self.matching(Or) {
+            let operation = self.previous();
+            let r: Expr = self.and()?;
+            expr = Logical {
+                l: Box::from(expr),
+                op: operation,
+                r: Box::from(r),
+            };
+        }
+        Ok(expr)
+    }
+    fn and(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.bit_or()?;
+        while self.matching(And) {
+            let operation = self.previous();
+            let r: Expr = self.bit_or()?;
+            expr = Logical {
+                l: Box::from(expr),
+                op: operation,
+                r: Box::from(r),
+            };
+        }
+        Ok(expr)
+    }
+    // Bitwise levels sit between and/equality like C, so x & 1 == 1 is x & (1 == 1)
+    fn bit_or(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.bit_xor()?;
+        while self.matching(Pipe) {
+            let operation = self.previous();
+            let r: Expr = self.bit_xor()?;
+            expr = Binary {
+                l: Box::from(expr),
+                op: operation,
+                r: Box::from(r),
+            };
+        }
+        Ok(expr)
+    }
+    fn bit_xor(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.bit_and()?;
+        while self.matching(Caret) {
+            let operation = self.previous();
+            let r: Expr = self.bit_and()?;
+            expr = Binary {
+                l: Box::from(expr),
+                op: operation,
+                r: Box::from(r),
+            };
+        }
+        Ok(expr)
+    }
+    fn bit_and(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.equality()?;
+        while self.matching(Amp) {
+            let operation = self.previous();
+            let r: Expr = self.equality()?;
+            expr = Binary {
+                l: Box::from(expr),
+                op: operation,
+                r: Box::from(r),
+            };
+        }
+        Ok(expr)
     }
     //Use our comparison func to assign expr, loop through w/ matching fn conditionals
-    fn equality(&mut self) -> Result<Expr, String> {
+    fn equality(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.comparison()?;
         while self.matchings(&[BangEqual, EqualEqual]) {
             let operation = self.previous();
@@ -95,11 +445,11 @@ impl Parser {
         }
         Ok(expr)
     }
-    fn comparison(&mut self) -> Result<Expr, String> {
-        let mut expr: Expr = self.term()?;
+    fn comparison(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr: Expr = self.shift()?;
         while self.matchings(&[Greater, GreaterEqual, Less, LessEqual]) {
             let operation: Token = self.previous();
-            let r: Expr = self.term()?;
+            let r: Expr = self.shift()?;
             expr = Binary {
                 l: Box::from(expr),
                 op: operation,
@@ -109,7 +459,21 @@ impl Parser {
         Ok(expr)
     }
 
-    fn term(&mut self) -> Result<Expr, String> {
+    // << and >> bind looser than + and - (1 << 2 + 1 == 8)
+    fn shift(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.term()?;
+        while self.matchings(&[LessLess, GreaterGreater]) {
+            let operation = self.previous();
+            let r: Expr = self.term()?;
+            expr = Binary {
+                l: Box::from(expr),
+                op: operation,
+                r: Box::from(r),
+            };
+        }
+        Ok(expr)
+    }
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr: Expr = self.factor()?;
 
         while self.matchings(&[Minus, Plus]) {
@@ -124,10 +488,10 @@ impl Parser {
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, String> {
+    fn factor(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr: Expr = self.unary()?;
 
-        while self.matchings(&[Slash, Star]) {
+        while self.matchings(&[Slash, Star, SlashSlash, Percent]) {
             let operation: Token = self.previous();
             let r: Expr = self.unary()?;
             expr = Binary {
@@ -138,66 +502,245 @@ impl Parser {
         }
         Ok(expr)
     }
-    fn unary(&mut self) -> Result<Expr, String> {
-        if self.matchings(&[Bang, Minus]) {
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
+        if self.matchings(&[PlusPlus, MinusMinus]) {
             let operation: Token = self.previous();
-            let r: Expr = self.unary()?;
+            let target: Expr = self.nested(Self::unary)?;
+            return increment(target, operation, false);
+        }
+        if self.matchings(&[Bang, Minus, Tilde]) {
+            let operation: Token = self.previous();
+            let r: Expr = self.nested(Self::unary)?;
             return Ok(Unary {
                 op: operation,
                 r: Box::from(r),
             });
         }
-        Ok(self.primary()?)
+        self.power()
+    }
+    // Binds tighter than a unary on its left (-2 ** 2 == -4) but the right side can be one
+    // (2 ** -1), right associative so 2 ** 3 ** 2 == 2 ** 9
+    fn power(&mut self) -> Result<Expr, Diagnostic> {
+        let expr = self.call()?;
+        if self.matching(StarStar) {
+            let operation: Token = self.previous();
+            let r: Expr = self.nested(Self::unary)?;
+            return Ok(Binary {
+                l: Box::from(expr),
+                op: operation,
+                r: Box::from(r),
+            });
+        }
+        Ok(expr)
+    }
+    // Primary followed by any number of (args), .name or [index], so a.b(1)[0].c works
+    fn call(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.matching(LParen) {
+                expr = self.finish_call(expr)?;
+            } else if self.matching(Dot) {
+                let name = self.consume(Identifier, "Expected property name after '.'")?;
+                expr = Get {
+                    object: Box::from(expr),
+                    name,
+                };
+            } else if self.matching(LBracket) {
+                let index = self.expression()?;
+                let bracket = self.consume(RBracket, "Expecting -=(']')=- after index")?;
+                expr = Index {
+                    object: Box::from(expr),
+                    bracket,
+                    index: Box::from(index),
+                };
+            } else {
+                break;
+            }
+        }
+        if self.matchings(&[PlusPlus, MinusMinus]) {
+            return increment(expr, self.previous(), true);
+        }
+        Ok(expr)
+    }
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, Diagnostic> {
+        let mut args: Vec<Expr> = Vec::new();
+        if !self.check(RParen) {
+            loop {
+                if args.len() >= 255 {
+                    return Err(Diagnostic::error(
+                        203,
+                        "Cannot have more than 255 arguments",
+                        self.peek().span,
+                    ));
+                }
+                args.push(self.expression()?);
+                if !self.matching(Comma) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(RParen, "Expecting -=(')')=- after arguments")?;
+        Ok(Call {
+            callee: Box::from(callee),
+            paren,
+            args,
+        })
     }
     // Leave off on
-    fn primary(&mut self) -> Result<Expr, String> {
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
         let t = self.peek();
-        let res;
-        match t.token_type {
+        let res = match t.token_type {
             LParen => {
                 self.advance();
                 let expr = self.expression()?;
                 self.consume(RParen, "Expecting -=(')')=- after expression")?;
-                res = Grouping {
+                Grouping {
                     expr: Box::from(expr),
                 }
             }
             True | False | Null | Number | StringLit => {
                 self.advance();
-                res = Literal {
-                    val: LiteralVal::token_fmt(t),
+                Literal {
+                    span: t.span,
+                    val: LiteralVal::token_fmt(t)?,
+                }
+            }
+            StringInterp => {
+                let mut parts: Vec<Expr> = Vec::new();
+                while self.matching(StringInterp) {
+                    let piece = self.previous();
+                    if self.check(InterpEnd) {
+                        let close = self.peek();
+                        return Err(Diagnostic::error(
+                            209,
+                            "Empty string interpolation",
+                            piece.span.to(close.span),
+                        )
+                        .with_note("put an expression inside ${ }, or write \\$ for a plain $"));
+                    }
+                    parts.push(Literal {
+                        span: piece.span,
+                        val: LiteralVal::token_fmt(piece)?,
+                    });
+                    parts.push(self.expression()?);
+                    self.consume(InterpEnd, "Expecting -=('}')=- to close string interpolation")?;
+                }
+                let end = self.consume(StringLit, "Expecting the rest of the string")?;
+                parts.push(Literal {
+                    span: end.span,
+                    val: LiteralVal::token_fmt(end)?,
+                });
+                Interpolation { parts }
+            }
+            LBracket => {
+                self.advance();
+                let bracket = self.previous();
+                let mut items: Vec<Expr> = Vec::new();
+                // trailing comma is fine: [1, 2, ]
+                while !self.check(RBracket) {
+                    items.push(self.expression()?);
+                    if !self.matching(Comma) {
+                        break;
+                    }
+                }
+                let close = self.consume(RBracket, "Expecting -=(']')=- after list items")?;
+                List {
+                    span: bracket.span.to(close.span),
+                    items,
+                }
+            }
+            // in an expression { is always a map, blocks only start statements
+            LBrace => {
+                self.advance();
+                let brace = self.previous();
+                let mut entries: Vec<(Expr, Expr)> = Vec::new();
+                while !self.check(RBrace) {
+                    let k = self.expression()?;
+                    self.consume(Colon, "Expecting -=(':')=- after map key")?;
+                    let v = self.expression()?;
+                    entries.push((k, v));
+                    if !self.matching(Comma) {
+                        break;
+                    }
+                }
+                let close = self.consume(RBrace, "Expecting -=('}')=- after map entries")?;
+                Map {
+                    span: brace.span.to(close.span),
+                    entries,
+                }
+            }
+            Super => {
+                self.advance();
+                let kw = self.previous();
+                self.consume(Dot, "Expecting -=('.')=- after 'super'")?;
+                let method = self.consume(Identifier, "Expected superclass method name")?;
+                Expr::Super {
+                    kw,
+                    method,
+                    slot: Cell::new(None),
+                }
+            }
+            This => {
+                self.advance();
+                Expr::This {
+                    kw: self.previous(),
+                    slot: Cell::new(None),
                 }
             }
             Identifier => {
                 self.advance();
-                res = Variable {
-                    name: self.previous(), 
+                Variable {
+                    name: self.previous(),
+                    slot: Cell::new(None),
                 }
             }
 
-
-            _ => return Err("Expected expression here".to_string()),
+            _ => {
+                return Err(Diagnostic::error(
+                    204,
+                    format!("Expected expression, found {}", Self::describe(&t)),
+                    t.span,
+                ))
+            }
         };
         Ok(res)
     }
-    fn consume(&mut self, ttype: TokenType, err: &str) -> Result<Token, String> {
+    fn consume(&mut self, ttype: TokenType, err: &str) -> Result<Token, Diagnostic> {
         let t = self.peek();
         if t.token_type == ttype {
             self.advance();
             let t = self.previous();
             Ok(t)
         } else {
-            Err(err.to_string())
+            let msg = format!("{}, found {}", err, Self::describe(&t));
+            // something missing at the end of a line gets pointed at right after the line
+            let prev = self.previous();
+            if self.current > 0 && t.span.line > prev.span.line {
+                let after = Span {
+                    offset: prev.span.offset + prev.span.len,
+                    len: 0,
+                    line: prev.span.line,
+                    col: prev.span.col + prev.lexeme.chars().count(),
+                };
+                return Err(Diagnostic::error(201, msg, after));
+            }
+            Err(Diagnostic::error(201, msg, t.span))
+        }
+    }
+    // How a token gets named in errors
+    fn describe(t: &Token) -> String {
+        match t.token_type {
+            Eof => "end of file".to_string(),
+            _ => format!("-=({})=-", t.lexeme),
         }
     }
     fn matching(&mut self, t: TokenType) -> bool {
         if self.is_at_end() {
             return false;
-        } else {
-            if self.peek().token_type == t {
-                self.advance();
-                return true;
-            }
+        }
+        if self.peek().token_type == t {
+            self.advance();
+            return true;
         }
         false
     }
@@ -230,31 +773,135 @@ impl Parser {
     fn is_at_end(&mut self) -> bool {
         self.peek().token_type == Eof
     }
-    // Gets current token
+    // Gets current token, running off the end (no Eof token given) reads as Eof
     fn peek(&self) -> Token {
-        self.tokens[self.current].clone()
+        match self.tokens.get(self.current) {
+            Some(t) => t.clone(),
+            None => self.eof(),
+        }
     }
     // unfortunately have to use clone instead of passing as ref due to how mut works in Rust
-    // Gets previous token
+    // Gets previous token, before anything is consumed that is just the first one
     fn previous(&mut self) -> Token {
-        self.tokens[self.current - 1].clone()
+        match self.tokens.get(self.current.saturating_sub(1)) {
+            Some(t) => t.clone(),
+            None => self.eof(),
+        }
+    }
+    // Stand in Eof sitting just past the last token
+    fn eof(&self) -> Token {
+        let span = match self.tokens.last() {
+            Some(t) => Span {
+                offset: t.span.offset + t.span.len,
+                len: 0,
+                line: t.span.line,
+                col: t.span.col + t.lexeme.chars().count(),
+            },
+            None => Span::default(),
+        };
+        Token::new_token(Eof, "".to_string(), None, span)
+    }
+    // Runs one level deeper, too deep is an error instead of a stack overflow
+    fn nested<T>(&mut self, f: fn(&mut Self) -> Result<T, Diagnostic>) -> Result<T, Diagnostic> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Diagnostic::error(205, "Code is nested too deeply", self.peek().span)
+                .with_note(format!("at most {} levels of nesting are allowed", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
     }
 
+    // Panic mode, skips to where the next statement should start: just after a ;, before a
+    // statement keyword or before the } closing the block we are in. Braces opened while
+    // skipping are skipped as a whole so their insides do not count
     fn synchronize(&mut self) {
-        self.advance();
-
+        let start = self.current;
+        let mut depth = 0;
         while !self.is_at_end() {
-            if self.previous().token_type == Semicolon {
-                ()
-            }
             match self.peek().token_type {
-                Class | Fun | Var | For | If | While | Print | Return => return,
-                _ => self.advance(),
-            };
+                // the { the error was found at starts a block of its own, parse that
+                LBrace if self.current == start => return,
+                LBrace => depth += 1,
+                RBrace if depth > 0 => depth -= 1,
+                RBrace if self.blocks > 0 => return,
+                Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                // always move at least one token so the same error cannot repeat forever
+                Class | Fun | Var | For | If | While | Print | Return
+                    if depth == 0 && self.current > start =>
+                {
+                    return
+                }
+                Identifier
+                    if depth == 0 && self.current > start && self.foreign_kw_at().is_some() =>
+                {
+                    return
+                }
+                _ => (),
+            }
+            self.advance();
         }
     }
 }
 
+// Turns the target of `=`, `+=`, `++`... into the matching assignment node
+fn assign_to(
+    target: Expr,
+    val: Expr,
+    update: Option<Update>,
+    op: &Token,
+) -> Result<Expr, Diagnostic> {
+    match target {
+        Variable { name, .. } => Ok(Assign {
+            name,
+            val: Box::from(val),
+            slot: Cell::new(None),
+            update,
+        }),
+        Get { object, name } => Ok(Set {
+            object,
+            name,
+            val: Box::from(val),
+            update,
+        }),
+        Index {
+            object,
+            bracket,
+            index,
+        } => Ok(IndexSet {
+            object,
+            bracket,
+            index,
+            val: Box::from(val),
+            update,
+        }),
+        _ => Err(Diagnostic::error(202, "Invalid assignment target", op.span)
+            .with_label(target.span(), "cannot assign to this")
+            .with_note("only variables, fields (a.b) and indexes (a[i]) can be assigned")),
+    }
+}
+
+// a++ / ++a / a-- / --a are a += 1 or a -= 1, the postfix ones give back the old value
+fn increment(target: Expr, op: Token, postfix: bool) -> Result<Expr, Diagnostic> {
+    let one = Literal {
+        val: LiteralVal::IntVal(1),
+        span: op.span,
+    };
+    let token_type = if op.token_type == PlusPlus { Plus } else { Minus };
+    let update = Update {
+        op: Token {
+            token_type,
+            ..op.clone()
+        },
+        postfix,
+    };
+    assign_to(target, one, Some(update), &op)
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -307,3 +954,200 @@ impl Parser {
 //         assert_eq!(se, "(== (- 8 2) (+ 5 1))");
 //     }
 // }
+
+// Fuzz style regression tests, random input must come back as Ok or diagnostics, never a panic
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{LiteralVal as ScanVal, Scanner};
+
+    const TYPES: [TokenType; 62] = [
+        LParen, RParen, LBrace, RBrace, LBracket, RBracket, Comma, Colon, Dot, Minus, Plus,
+        Semicolon, Slash, Star, Percent, Amp, Pipe, Caret, Tilde, Bang, BangEqual, Equal,
+        EqualEqual, Greater, GreaterEqual, Less, LessEqual, StarStar, SlashSlash, LessLess,
+        GreaterGreater, PlusEqual, MinusEqual, StarEqual, SlashEqual, PercentEqual, PlusPlus,
+        MinusMinus, Identifier, StringLit, StringInterp, InterpEnd, Number, And, Break, Class,
+        Continue, Else, False, Fun, For, If, Null, Or, Print, Return, Super, This, True, Var, While, Eof,
+    ];
+
+    // xorshift64, fixed seed so failures can be replayed
+    struct Rng(u64);
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn token(rng: &mut Rng, i: usize) -> Token {
+        let tt = TYPES[rng.below(TYPES.len())];
+        // literals are sometimes left off or the wrong kind, like a broken scanner would give
+        let literal = match (tt, rng.below(4)) {
+            (_, 0) => None,
+            (Number, _) => Some(ScanVal::FVal(rng.below(100) as f64)),
+            (StringLit | StringInterp, _) => Some(ScanVal::StringVal("s".to_string())),
+            (Identifier, _) => Some(ScanVal::IdentifierVal("x".to_string())),
+            _ => Some(ScanVal::IntVal(1)),
+        };
+        let span = Span {
+            offset: i,
+            len: 1,
+            line: 1,
+            col: i + 1,
+        };
+        Token::new_token(tt, format!("{:?}", tt), literal, span)
+    }
+
+    fn random_tokens(rng: &mut Rng) -> Vec<Token> {
+        let len = rng.below(48);
+        let mut tokens: Vec<Token> = (0..len).map(|i| token(rng, i)).collect();
+        // most streams end in Eof like the scanner's do, some just stop
+        if rng.below(4) != 0 {
+            tokens.push(Token::new_token(Eof, "".to_string(), None, Span::default()));
+        }
+        tokens
+    }
+
+    #[test]
+    fn random_token_streams_do_not_panic() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..20_000 {
+            let mut p = Parser::new(random_tokens(&mut rng));
+            if let Err(errs) = p.parse() {
+                assert!(!errs.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn random_source_does_not_panic() {
+        let pieces = [
+            "let", "fnc", "cls", "if", "else", "while", "for", "ret", "print", "this", "super",
+            "x", "f", "1", "2.5", "\"s\"", "\"${", "}", "{", "(", ")", "[", "]", ",", ":", ";",
+            ".", "=", "==", "!", "-", "+", "*", "/", "<", ">=", "and", "or", "null", " ", "\n",
+            "%", "**", "//", "#", "/*", "*/", "&", "|", "^", "~", "<<", ">>",
+            "0x1F", "0b", "1_000", "2.5e3", "1e", "+=", "-=", "*=", "/=", "%=", "++", "--",
+        ];
+        let mut rng = Rng(42);
+        for _ in 0..5_000 {
+            let src: String = (0..rng.below(40)).map(|_| pieces[rng.below(pieces.len())]).collect();
+            if let Ok(tokens) = Scanner::new(&src).scan_tokens() {
+                let _ = Parser::new(tokens).parse();
+            }
+        }
+    }
+
+    #[test]
+    fn empty_stream_parses_to_nothing() {
+        let stmnts = Parser::new(vec![]).parse();
+        assert!(matches!(stmnts, Ok(s) if s.is_empty()));
+    }
+
+    #[test]
+    fn number_without_literal_is_an_error() {
+        let tokens = vec![
+            Token::new_token(Print, "print".to_string(), None, Span::default()),
+            Token::new_token(Number, "1".to_string(), None, Span::default()),
+            Token::new_token(Semicolon, ";".to_string(), None, Span::default()),
+        ];
+        let errs = Parser::new(tokens).parse().err().unwrap();
+        assert_eq!(errs.iter().next().unwrap().code, 206);
+    }
+
+    fn parse_errs(src: &str) -> Diagnostics {
+        let tokens = Scanner::new(src).scan_tokens().unwrap();
+        Parser::new(tokens).parse().err().unwrap()
+    }
+
+    #[test]
+    fn recovers_at_statements_and_blocks() {
+        let src = "let = 1;\nprint 2\n{\n let y = ;\n print 4;\n fnc f( { ret 1; }\n}\nlet m = {\"a\": 1;\nprint (3;\n";
+        let lines: Vec<usize> = parse_errs(src).iter().map(|d| d.span.line).collect();
+        assert_eq!(lines, vec![1, 2, 4, 6, 8, 9]);
+    }
+
+    #[test]
+    fn statement_after_error_is_kept() {
+        // only the first let is bad, the print after it must not get swallowed
+        let errs = parse_errs("let = 1; print x +;");
+        assert_eq!(errs.len(), 2);
+    }
+
+    #[test]
+    fn cascades_are_capped() {
+        let errs = parse_errs(&"let = 1;\n".repeat(100));
+        assert_eq!(errs.len(), MAX_ERRORS + 1);
+        assert_eq!(errs.iter().last().unwrap().code, 207);
+    }
+
+    #[test]
+    fn errors_say_what_was_found() {
+        let errs = parse_errs("print (1;");
+        assert!(errs.iter().next().unwrap().msg.ends_with("found -=(;)=-"));
+        let errs = parse_errs("print 1");
+        assert!(errs.iter().next().unwrap().msg.ends_with("found end of file"));
+    }
+
+    #[test]
+    fn foreign_keywords_get_a_hint() {
+        let errs = parse_errs("var x = 1;\nfunction f(a) { return a; }\nclass A {}\nlet ok = 1;");
+        let hints: Vec<&str> = errs.iter().map(|d| d.notes[0].as_str()).collect();
+        assert_eq!(hints, vec!["did you mean `let`?", "did you mean `fnc`?", "did you mean `cls`?"]);
+    }
+
+    #[test]
+    fn brace_with_key_colon_is_a_map() {
+        let parse = |src: &str| Parser::new(Scanner::new(src).scan_tokens().unwrap()).parse();
+        for src in ["{ k: 1 };", "{ \"a${k}\": 1, 2: 3 };", "{ k + 1: 1 };", "{ \"a\": 1 };"] {
+            let st = parse(src).unwrap();
+            assert!(matches!(st[0], Statement::Expression { .. }), "{}", src);
+        }
+        for src in ["{ k = 2; }", "{ k; }", "{ print 1; }", "{ }"] {
+            let st = parse(src).unwrap();
+            assert!(matches!(st[0], Statement::Block { .. }), "{}", src);
+        }
+    }
+
+    #[test]
+    fn interpolation_ends_at_its_brace() {
+        // the dangling + hits the closing }, it must not borrow the rest of the string
+        let errs = parse_errs("print \"${\"x\" +}a\" \"b\";");
+        let e = errs.iter().next().unwrap();
+        assert_eq!((e.code, e.span.col), (204, 15));
+        let errs = parse_errs("print \"${1 2}\";");
+        assert!(errs.iter().next().unwrap().msg.starts_with("Expecting -=('}')=- to close"));
+        // an empty ${} is its own error, covering the ${ through the }
+        let e = parse_errs("print \"a${}b\";").iter().next().unwrap().clone();
+        assert_eq!((e.code, e.span.col, e.span.len), (209, 7, 5));
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        // debug builds use a lot of stack per level, same headroom main gives the interpreter
+        std::thread::Builder::new()
+            .stack_size(crate::STACK_SIZE)
+            .spawn(deep_nesting)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+    fn deep_nesting() {
+        let src = format!("print {}1{};", "(".repeat(5_000), ")".repeat(5_000));
+        let tokens = Scanner::new(&src).scan_tokens().unwrap();
+        let errs = Parser::new(tokens).parse().err().unwrap();
+        assert_eq!(errs.iter().next().unwrap().code, 205);
+
+        let src = format!("{}print 1;{}", "{".repeat(5_000), "}".repeat(5_000));
+        let tokens = Scanner::new(&src).scan_tokens().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+
+        let src = format!("print {}1;", "-".repeat(5_000));
+        let tokens = Scanner::new(&src).scan_tokens().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+}
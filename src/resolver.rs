@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::expr::Expr;
+use crate::scanner::{Span, Token};
+use crate::statement::Statement;
+
+// Where a local lives at runtime, depth = scopes to walk out, index = slot in that scope
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FncType {
+    NoFnc,
+    Fnc,
+    Method,
+    Init,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    NoClass,
+    Class,
+    Subclass,
+}
+
+// Semantic pass run before interpreting, binds each local variable use to its Slot.
+// Anything left unbound is a global and gets looked up by name
+pub struct Resolver {
+    // name -> (slot index, finished initializing, where it was declared)
+    scopes: Vec<HashMap<String, (usize, bool, Span)>>,
+    current_fnc: FncType,
+    current_class: ClassType,
+    errs: Diagnostics,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: Vec::new(),
+            current_fnc: FncType::NoFnc,
+            current_class: ClassType::NoClass,
+            errs: Diagnostics::new(),
+        }
+    }
+    pub fn resolve(&mut self, statements: &[Statement]) -> Result<(), Diagnostics> {
+        for st in statements {
+            self.statement(st);
+        }
+        if self.errs.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errs))
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Expression { expr } | Statement::Print { expr } => self.expr(expr),
+            Statement::Var { t, init } => {
+                self.declare(t);
+                self.expr(init);
+                self.define(t);
+            }
+            Statement::Block { statements } => {
+                self.begin_scope();
+                for st in statements {
+                    self.statement(st);
+                }
+                self.end_scope();
+            }
+            Statement::If {
+                cond,
+                then_b,
+                else_b,
+            } => {
+                self.expr(cond);
+                self.statement(then_b);
+                if let Some(e) = else_b {
+                    self.statement(e);
+                }
+            }
+            Statement::While { cond, body, step } => {
+                self.expr(cond);
+                self.statement(body);
+                if let Some(s) = step {
+                    self.expr(s);
+                }
+            }
+            Statement::Break { .. } | Statement::Continue { .. } => {}
+            Statement::Function { name, params, body } => {
+                // defined straight away so the function can call itself
+                self.declare(name);
+                self.define(name);
+                self.function(params, body, FncType::Fnc);
+            }
+            Statement::Return { t, val } => {
+                if self.current_fnc == FncType::NoFnc {
+                    self.errs.push(Diagnostic::error(
+                        301,
+                        format!("-=({})=- is not inside a function", t.lexeme),
+                        t.span,
+                    ));
+                }
+                if let Some(v) = val {
+                    if self.current_fnc == FncType::Init {
+                        self.errs.push(
+                            Diagnostic::error(302, "Cannot ret a value from init", t.span)
+                                .with_label(v.span(), "this value")
+                                .with_note("init always returns the new instance, use a bare ret;"),
+                        );
+                    }
+                    self.expr(v);
+                }
+            }
+            Statement::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let enclosing = self.current_class;
+                self.current_class = ClassType::Class;
+                self.declare(name);
+                self.define(name);
+                if let Some(sup) = superclass {
+                    if let Expr::Variable { name: sup_name, .. } = sup {
+                        if sup_name.lexeme == name.lexeme {
+                            self.errs.push(
+                                Diagnostic::error(
+                                    303,
+                                    format!("Class -=({})=- cannot inherit from itself", name.lexeme),
+                                    sup_name.span,
+                                )
+                                .with_label(name.span, "class declared here"),
+                            );
+                        }
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.expr(sup);
+                    // matches the scope the interpreter wraps around subclass methods
+                    self.begin_scope();
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.insert("super".to_string(), (0, true, Span::default()));
+                    }
+                }
+                // methods see `this` in slot 0 of a scope around them, see Function::bind
+                self.begin_scope();
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert("this".to_string(), (0, true, Span::default()));
+                }
+                for m in methods {
+                    if let Statement::Function { name, params, body } = m {
+                        let fnc_type = if name.lexeme == "init" {
+                            FncType::Init
+                        } else {
+                            FncType::Method
+                        };
+                        self.function(params, body, fnc_type);
+                    }
+                }
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.current_class = enclosing;
+            }
+        }
+    }
+
+    // Params and body share one scope, same as the call enviro built in Function::call
+    fn function(&mut self, params: &[Token], body: &[Statement], fnc_type: FncType) {
+        let enclosing = self.current_fnc;
+        self.current_fnc = fnc_type;
+        self.begin_scope();
+        for p in params {
+            self.declare(p);
+            self.define(p);
+        }
+        for st in body {
+            self.statement(st);
+        }
+        self.end_scope();
+        self.current_fnc = enclosing;
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Variable { name, slot } => {
+                if let Some(scope) = self.scopes.last() {
+                    if let Some((_, false, decl)) = scope.get(&name.lexeme) {
+                        self.errs.push(
+                            Diagnostic::error(
+                                304,
+                                format!(
+                                    "Cannot read local variable -=({})=- in its own initializer",
+                                    name.lexeme
+                                ),
+                                name.span,
+                            )
+                            .with_label(*decl, "declared here"),
+                        );
+                    }
+                }
+                slot.set(self.resolve_local(name));
+            }
+            Expr::Assign { name, val, slot, .. } => {
+                self.expr(val);
+                slot.set(self.resolve_local(name));
+            }
+            Expr::Binary { l, r, .. } | Expr::Logical { l, r, .. } => {
+                self.expr(l);
+                self.expr(r);
+            }
+            Expr::Grouping { expr } => self.expr(expr),
+            Expr::Literal { .. } => {}
+            Expr::Unary { r, .. } => self.expr(r),
+            Expr::Call { callee, args, .. } => {
+                self.expr(callee);
+                for a in args {
+                    self.expr(a);
+                }
+            }
+            Expr::Get { object, .. } => self.expr(object),
+            Expr::Interpolation { parts } => {
+                for p in parts {
+                    self.expr(p);
+                }
+            }
+            Expr::List { items, .. } => {
+                for i in items {
+                    self.expr(i);
+                }
+            }
+            Expr::Map { entries, .. } => {
+                for (k, v) in entries {
+                    self.expr(k);
+                    self.expr(v);
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.expr(object);
+                self.expr(index);
+            }
+            Expr::IndexSet {
+                object, index, val, ..
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(val);
+            }
+            Expr::Set { object, val, .. } => {
+                self.expr(val);
+                self.expr(object);
+            }
+            Expr::This { kw, slot } => {
+                if self.current_class == ClassType::NoClass {
+                    self.errs.push(Diagnostic::error(
+                        305,
+                        "-=(this)=- is not inside a method",
+                        kw.span,
+                    ));
+                    return;
+                }
+                slot.set(self.resolve_local(kw));
+            }
+            Expr::Super { kw, slot, .. } => {
+                match self.current_class {
+                    ClassType::Subclass => slot.set(self.resolve_local(kw)),
+                    _ => self.errs.push(Diagnostic::error(
+                        306,
+                        "-=(super)=- is not inside a subclass",
+                        kw.span,
+                    )),
+                }
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+    // Adds to the innermost scope, not usable until define is called
+    fn declare(&mut self, name: &Token) {
+        let scope = match self.scopes.last_mut() {
+            Some(s) => s,
+            None => return,
+        };
+        if let Some((_, _, first)) = scope.get(&name.lexeme) {
+            self.errs.push(
+                Diagnostic::error(
+                    307,
+                    format!("Variable -=({})=- is already declared in this scope", name.lexeme),
+                    name.span,
+                )
+                .with_label(*first, "first declared here"),
+            );
+            return;
+        }
+        let index = scope.len();
+        scope.insert(name.lexeme.clone(), (index, false, name.span));
+    }
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            if let Some(entry) = scope.get_mut(&name.lexeme) {
+                entry.1 = true;
+            }
+        }
+    }
+    // Innermost scope holding the name wins, None means global
+    fn resolve_local(&self, name: &Token) -> Option<Slot> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some((index, _, _)) = scope.get(&name.lexeme) {
+                return Some(Slot {
+                    depth,
+                    index: *index,
+                });
+            }
+        }
+        None
+    }
+}
+
+// Slots the resolver hands out have to line up with the order Enviro::define fills them at
+// runtime (block lets, fnc params, the this/super scopes), so most of these run the script
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostics;
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn resolve(src: &str) -> Result<Vec<Statement>, Diagnostics> {
+        let tokens = Scanner::new(src).scan_tokens()?;
+        let statements = Parser::new(tokens).parse()?;
+        Resolver::new().resolve(&statements)?;
+        Ok(statements)
+    }
+    // Runs the script then gives back the printed form of a global
+    fn run_and_get(src: &str, name: &str) -> String {
+        let mut intr = Interpreter::new();
+        for st in resolve(src).unwrap() {
+            intr.interpret_statement(st).unwrap();
+        }
+        let tokens = Scanner::new(name).scan_tokens().unwrap();
+        let expr = Parser::new(tokens).expression().unwrap();
+        intr.interpret(expr).unwrap().format_str()
+    }
+    fn err_codes(src: &str) -> Vec<u16> {
+        resolve(src).err().unwrap().iter().map(|e| e.code).collect()
+    }
+
+    #[test]
+    fn shadowing_in_blocks_and_functions() {
+        let src = r#"
+            let a = "g"; let out = "";
+            { let a = "b1"; { let a = "b2"; out = out + a; } out = out + a; }
+            out = out + a;
+            fnc f(a) { let b = a; { let a = "f"; out = out + a; } out = out + a + b; }
+            f("p");
+        "#;
+        assert_eq!(run_and_get(src, "out"), "b2b1gfpp");
+    }
+
+    #[test]
+    fn closures_bind_where_they_are_declared() {
+        // show() was resolved before the block's own a existed, so it keeps seeing the global
+        let src = r#"
+            let a = "global"; let out = "";
+            { fnc show() { ret a; } out = out + show(); let a = "block"; out = out + show(); }
+        "#;
+        assert_eq!(run_and_get(src, "out"), "globalglobal");
+        // a local assigned after the closure is made is still the same variable
+        let src = r#"
+            fnc make() { let x = 1; fnc get() { ret x; } x = 2; ret get; }
+            let v = make()();
+        "#;
+        assert_eq!(run_and_get(src, "v"), "2");
+    }
+
+    #[test]
+    fn this_and_super_from_nested_closures() {
+        let src = r#"
+            cls A { name() { ret "A"; } }
+            cls B < A {
+                init() { this.n = 1; }
+                name() { ret "B"; }
+                field() { fnc outer() { fnc inner() { ret this.n; } ret inner; } ret outer()(); }
+                both() { let pad = 0; fnc s() { ret super.name() + this.name(); } ret s; }
+            }
+            let b = B();
+            let n = b.field();
+            let s = b.both()();
+        "#;
+        assert_eq!(run_and_get(src, "n"), "1");
+        assert_eq!(run_and_get(src, "s"), "AB");
+    }
+
+    #[test]
+    fn resolver_errors() {
+        assert_eq!(err_codes("ret 1;"), [301]);
+        assert_eq!(err_codes("cls A { init() { ret 1; } }"), [302]);
+        assert_eq!(err_codes("cls A < A { }"), [303]);
+        assert_eq!(err_codes("{ let a = 1; { let a = a; } }"), [304]);
+        assert_eq!(err_codes("print this;"), [305]);
+        assert_eq!(err_codes("cls A { f() { super.f(); } }"), [306]);
+        assert_eq!(err_codes("{ let a = 1; let a = 2; }"), [307]);
+        // globals can be redeclared, only locals clash
+        assert!(resolve("let a = 1; let a = 2;").is_ok());
+        // every error is reported in one pass
+        let src = "ret 1;\nprint this;\nfnc f() { let x = 1; let x = 2; }";
+        assert_eq!(err_codes(src), [301, 305, 307]);
+
+        let errs = resolve("{\n  let a = 1;\n  let a = 2;\n}").err().unwrap();
+        let err = errs.iter().next().unwrap();
+        assert_eq!((err.span.line, err.labels[0].span.line), (3, 2));
+    }
+}
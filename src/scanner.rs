@@ -2,27 +2,174 @@
 #![allow(unused_variables)]
 use std::collections::HashMap;
 use std::string::String;
+use crate::diagnostic::{Diagnostic, Diagnostics};
 
 // Checks if numeric / can parse as digit
 fn is_digit(c: char) -> bool {
-    return (c as u8) >= ('0' as u8) && (c as u8) <= ('9' as u8);
+    c.is_ascii_digit()
 }
-// Checks if it is alpha
+// Checks if it can start an identifier (any unicode letter, so café or λ work)
 fn is_alpha(c: char) -> bool {
-    return ((c as u8) >= ('a' as u8) && (c as u8) <= ('z' as u8))
-        || ((c as u8) >= ('A' as u8) && (c as u8) <= ('Z' as u8))
-        || c == '_';
+    c.is_alphabetic() || c == '_'
 }
 // Checks if alphanumeric
 fn is_alpha_num(c: char) -> bool {
-    return is_alpha(c) || is_digit(c);
+    is_alpha(c) || c.is_alphanumeric()
+}
+
+// Turns the escapes in string text into real chars, at is where raw starts (for errors)
+fn unescape(raw: &str, at: Span) -> Result<String, Diagnostic> {
+    let mut out = String::new();
+    let (mut offset, mut line, mut col) = (at.offset, at.line, at.col);
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            if c == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+            offset += c.len_utf8();
+            out.push(c);
+            continue;
+        }
+        let esc_at = offset;
+        let esc_col = col;
+        let mut esc_len = 1;
+        col += 2;
+        let next = chars.next();
+        esc_len += next.map(|c| c.len_utf8()).unwrap_or(0);
+        let esc_span = |len: usize| Span {
+            offset: esc_at,
+            len,
+            line,
+            col: esc_col,
+        };
+        match next {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            Some('$') => out.push('$'),
+            Some('u') => {
+                let mut hex = String::new();
+                let mut closed = false;
+                if chars.peek() == Some(&'{') {
+                    chars.next();
+                    col += 1;
+                    esc_len += 1;
+                    for h in chars.by_ref() {
+                        col += 1;
+                        esc_len += h.len_utf8();
+                        if h == '}' {
+                            closed = true;
+                            break;
+                        }
+                        hex.push(h);
+                    }
+                }
+                let ch = if closed && !hex.is_empty() && hex.len() <= 6 {
+                    u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                } else {
+                    None
+                };
+                match ch {
+                    Some(ch) => out.push(ch),
+                    None => {
+                        return Err(Diagnostic::error(
+                            104,
+                            format!("Bad unicode escape -=(\\u{{{}}})=-", hex),
+                            esc_span(esc_len),
+                        )
+                        .with_note("unicode escapes look like \\u{1F600}, 1-6 hex digits"))
+                    }
+                }
+            }
+            Some(other) => {
+                return Err(Diagnostic::error(
+                    103,
+                    format!("Unknown escape -=(\\{})=-", other),
+                    esc_span(esc_len),
+                )
+                .with_note("valid escapes are \\n \\t \\r \\\\ \\\" \\$ and \\u{...}"))
+            }
+            None => {
+                return Err(Diagnostic::error(
+                    103,
+                    "Lone -=(\\)=- at end of string",
+                    esc_span(esc_len),
+                ))
+            }
+        }
+        offset += esc_len;
+    }
+    Ok(out)
+}
+
+// For """ strings: drops the newline after the opening quotes, the blank line before the
+// closing quotes, and the indentation every non blank line shares. Blank lines come out empty
+fn dedent(raw: &str) -> String {
+    let mut lines: Vec<&str> = raw.split('\n').collect();
+    if lines.len() > 1 && lines[0].trim().is_empty() {
+        lines.remove(0);
+    }
+    if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty() {
+        lines.pop();
+    }
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|l| {
+            if l.trim().is_empty() {
+                return "";
+            }
+            // at most indent of the leading spaces/tabs, by char so the cut is on a boundary
+            let cut = l
+                .char_indices()
+                .take_while(|&(i, c)| i < indent && (c == ' ' || c == '\t'))
+                .map(|(i, c)| i + c.len_utf8())
+                .last()
+                .unwrap_or(0);
+            &l[cut..]
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+// Keywords from other languages that people type out of habit, mapped to ours.
+// They still scan as identifiers, this is only used to hint at the right word in errors
+pub fn foreign_kw(word: &str) -> Option<&'static str> {
+    match word {
+        "var" | "const" | "val" | "mut" | "local" => Some("let"),
+        "function" | "func" | "fn" | "def" | "fun" | "lambda" => Some("fnc"),
+        "class" | "struct" => Some("cls"),
+        "return" => Some("ret"),
+        "nil" | "None" | "NULL" | "undefined" | "nullptr" => Some("null"),
+        "True" => Some("true"),
+        "False" => Some("false"),
+        "self" => Some("this"),
+        "elif" | "elsif" | "elseif" => Some("else if"),
+        "echo" | "puts" | "println" | "printf" => Some("print"),
+        "extends" => Some("<"),
+        "not" => Some("!"),
+        _ => None,
+    }
 }
 
 fn get_kws_hash() -> HashMap<&'static str, TokenType> {
     // NAMES for keywords
     HashMap::from([
         ("and", And),
+        ("break", Break),
         ("cls", Class),
+        ("continue", Continue),
         ("else", Else),
         ("true", True),
         ("false", False),
@@ -43,10 +190,17 @@ fn get_kws_hash() -> HashMap<&'static str, TokenType> {
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
+    // byte offsets into source, always on a char boundary
     start: usize,
     current: usize,
     line: usize,
+    // column (in chars) of current, and where the token being scanned began
+    col: usize,
+    start_line: usize,
+    start_col: usize,
     kws: HashMap<&'static str, TokenType>,
+    // one entry per open ${ ... }, counts the { } pairs opened inside it
+    interps: Vec<usize>,
 }
 
 impl Scanner {
@@ -58,58 +212,137 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            col: 1,
+            start_line: 1,
+            start_col: 1,
             kws: get_kws_hash(),
+            interps: vec![],
+        }
+    }
+    // Scans only from offset on (which sits at the start of line), tokens still get spans
+    // into all of s. The REPL keeps every line typed so old spans can be drawn later
+    pub fn new_at(s: &str, offset: usize, line: usize) -> Self {
+        Self {
+            start: offset,
+            current: offset,
+            line,
+            start_line: line,
+            ..Self::new(s)
         }
     }
     // Scans the token information from struct
-    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, String> {
-        // vec to check for errors
-        let mut errs = vec![];
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, Diagnostics> {
+        // collects every error so they all get reported
+        let mut errs = Diagnostics::new();
         while !self.is_at_end() {
             // beginning of next lexeme
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_col = self.col;
             match self.scan_token() {
                 Ok(_) => (),
                 Err(e) => errs.push(e),
             }
         }
+        if !self.interps.is_empty() {
+            errs.push(
+                Diagnostic::error(
+                    106,
+                    "Unterminated string interpolation, missing -=('}')=-",
+                    self.current_span(),
+                )
+                .with_note("every ${ needs a matching }"),
+            );
+        }
         // token creation
         self.tokens.push(Token {
             token_type: Eof,
             lexeme: "".to_string(),
             literal: None,
-            line_num: self.line,
+            span: self.current_span(),
         });
-        // makes err vec proper
-        if errs.len() > 0 {
-            let mut join = "".to_string();
-            for e in errs {
-                join.push_str(&e);
-                join.push_str("\n");
-            }
-            return Err(join);
+        if !errs.is_empty() {
+            return Err(errs);
         }
-        // needs to be cloned (set Clone attr w/ derive)
-        Ok(self.tokens.clone())
+        Ok(std::mem::take(&mut self.tokens))
     }
     // checks if at end of line
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
     // Scans a token data char by char
-    fn scan_token(&mut self) -> Result<(), String> {
+    fn scan_token(&mut self) -> Result<(), Diagnostic> {
         let c = self.advance();
         match c {
             '(' => self.add_token(LParen),
             ')' => self.add_token(RParen),
-            '{' => self.add_token(LBrace),
-            '}' => self.add_token(RBrace),
+            '{' => {
+                if let Some(depth) = self.interps.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(LBrace);
+            }
+            '}' => match self.interps.last_mut() {
+                // closes a ${ ... }, pick the string back up where it left off
+                Some(0) => {
+                    self.interps.pop();
+                    self.add_token(InterpEnd);
+                    self.string()?;
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(RBrace);
+                }
+                None => self.add_token(RBrace),
+            },
+            '[' => self.add_token(LBracket),
+            ']' => self.add_token(RBracket),
             ',' => self.add_token(Comma),
+            ':' => self.add_token(Colon),
             '.' => self.add_token(Dot),
-            '-' => self.add_token(Minus),
-            '+' => self.add_token(Plus),
+            '-' => {
+                let t = if self.match_char('=') {
+                    MinusEqual
+                } else if self.match_char('-') {
+                    MinusMinus
+                } else {
+                    Minus
+                };
+                self.add_token(t);
+            }
+            '+' => {
+                let t = if self.match_char('=') {
+                    PlusEqual
+                } else if self.match_char('+') {
+                    PlusPlus
+                } else {
+                    Plus
+                };
+                self.add_token(t);
+            }
             ';' => self.add_token(Semicolon),
-            '*' => self.add_token(Star),
+            '%' => {
+                let t = if self.match_char('=') {
+                    PercentEqual
+                } else {
+                    Percent
+                };
+                self.add_token(t);
+            }
+            '&' => self.add_token(Amp),
+            '|' => self.add_token(Pipe),
+            '^' => self.add_token(Caret),
+            '~' => self.add_token(Tilde),
+            '*' => {
+                let t = if self.match_char('*') {
+                    StarStar
+                } else if self.match_char('=') {
+                    StarEqual
+                } else {
+                    Star
+                };
+                self.add_token(t);
+            }
             '!' => {
                 let t = if self.match_char('=') {
                     BangEqual
@@ -129,6 +362,8 @@ impl Scanner {
             '<' => {
                 let t = if self.match_char('=') {
                     LessEqual
+                } else if self.match_char('<') {
+                    LessLess
                 } else {
                     Less
                 };
@@ -137,25 +372,43 @@ impl Scanner {
             '>' => {
                 let t = if self.match_char('=') {
                     GreaterEqual
+                } else if self.match_char('>') {
+                    GreaterGreater
                 } else {
                     Greater
                 };
                 self.add_token(t);
             }
+            // # comment runs to the end of the line
+            '#' => {
+                while self.peek() != '\n' && !self.is_at_end() {
+                    self.advance();
+                }
+            }
             '/' => {
-                if self.match_char('/') {
-                    //NOTE: Handled weird before, may have to chang && expr
-                    while self.peek() != '\n' && !self.is_at_end() {
-                        self.advance();
-                    }
+                if self.match_char('*') {
+                    self.block_comment()?;
+                } else if self.match_char('/') {
+                    self.add_token(SlashSlash);
+                } else if self.match_char('=') {
+                    self.add_token(SlashEqual);
                 } else {
                     self.add_token(Slash);
                 }
             }
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => self.newline(),
             // string handling
-            '"' => self.string()?,
+            '"' => {
+                if self.peek() == '"' && self.peek_next() == '"' {
+                    self.advance();
+                    self.advance();
+                    self.triple_string()?;
+                } else {
+                    self.string()?;
+                }
+            }
+            'r' if self.peek() == '"' => self.raw_string()?,
             // default case
             c => {
                 if is_digit(c) {
@@ -163,14 +416,40 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier()?;
                 } else {
-                    return Err(format!("Bad char at line {}: {}", self.line, c));
+                    return Err(Diagnostic::error(
+                        101,
+                        format!("Bad char -=({})=-", c),
+                        self.token_span(),
+                    ));
                 }
             } //NOTE: This may have to be changed later
         }
         Ok(())
     }
+    // /* ... */ can span lines and nest, so commenting out code that has one inside works
+    fn block_comment(&mut self) -> Result<(), Diagnostic> {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                return Err(Diagnostic::error(107, "Unterminated block comment", self.token_span())
+                    .with_note("every /* needs a matching */"));
+            }
+            if self.peek() == '/' && self.peek_next() == '*' {
+                self.advance();
+                self.advance();
+                depth += 1;
+            } else if self.peek() == '*' && self.peek_next() == '/' {
+                self.advance();
+                self.advance();
+                depth -= 1;
+            } else if self.advance() == '\n' {
+                self.newline();
+            }
+        }
+        Ok(())
+    }
     // Handles num lits
-    fn identifier(&mut self) -> Result<(), String> {
+    fn identifier(&mut self) -> Result<(), Diagnostic> {
         while is_alpha_num(self.peek()) {
             self.advance();
         }
@@ -182,81 +461,252 @@ impl Scanner {
         }
         Ok(())
     }
-    fn number(&mut self) -> Result<(), String> {
+    // No . makes an int literal, with one it is a float
+    // Ints and Floats: 255, 0xFF, 0b1111_1111, 0o377, 1_000_000, 2.5, 6.02e23.
+    // The first digit is already consumed
+    fn number(&mut self) -> Result<(), Diagnostic> {
+        let radix = match self.peek() {
+            _ if !self.source[self.start..].starts_with('0') => 10,
+            'x' | 'X' => 16,
+            'b' | 'B' => 2,
+            'o' | 'O' => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            return self.radix_number(radix);
+        }
         // checks if more to num (ex: 2 -> 27 -> 273 -> END = 273)
-        while is_digit(self.peek()) {
+        while is_digit(self.peek()) || self.peek() == '_' {
             self.advance();
         }
+        let mut is_float = false;
         if self.peek() == '.' && is_digit(self.peek_next()) {
+            is_float = true;
             self.advance();
 
-            while is_digit(self.peek()) {
+            while is_digit(self.peek()) || self.peek() == '_' {
                 self.advance();
             }
         }
+        let mut exp_start = None;
+        if matches!(self.peek(), 'e' | 'E') {
+            is_float = true;
+            self.advance();
+            if matches!(self.peek(), '+' | '-') {
+                self.advance();
+            }
+            exp_start = Some(self.current);
+            while is_digit(self.peek()) || self.peek() == '_' {
+                self.advance();
+            }
+        }
+        // letters stuck to the end (12abc) belong to the bad literal, not a new identifier
+        let end = self.current;
+        while is_alpha_num(self.peek()) {
+            self.advance();
+        }
+        let s = &self.source[self.start..self.current];
+        if exp_start == Some(end) {
+            return Err(self.bad_number(format!("Exponent of -=({})=- has no digits", s)));
+        }
+        if end != self.current {
+            return Err(self.bad_number(format!("Invalid number literal -=({})=-", s)));
+        }
+        let mut parts = s.split(['.', 'e', 'E', '+', '-']).filter(|p| !p.is_empty());
+        if parts.any(|p| p.split('_').any(str::is_empty)) {
+            return Err(self
+                .bad_number(format!("Misplaced -=(_)=- in number literal -=({})=-", s))
+                .with_note("_ can only go between two digits, ex 1_000_000"));
+        }
+        let clean = s.replace('_', "");
+        let lit = if is_float {
+            clean.parse::<f64>().ok().filter(|f| f.is_finite()).map(FVal)
+        } else {
+            clean.parse::<i64>().ok().map(IntVal)
+        };
+        match lit {
+            Some(lit) => self.add_token_p2(Number, Some(lit)),
+            None if !is_float => {
+                return Err(self
+                    .bad_number(format!("Integer literal -=({})=- is too large", s))
+                    .with_note(format!("Ints go up to {}, add a .0 to make it a Float", i64::MAX)))
+            }
+            None => return Err(self.bad_number(format!("Float literal -=({})=- is too large", s))),
+        }
+        Ok(())
+    }
+    // 0x / 0b / 0o literals, always Ints. Peek is on the x/b/o
+    fn radix_number(&mut self, radix: u32) -> Result<(), Diagnostic> {
+        self.advance();
+        while is_alpha_num(self.peek()) {
+            self.advance();
+        }
         let s = &self.source[self.start..self.current];
-        let v = s.parse::<f64>();
-        match v {
-            Ok(v) => self.add_token_p2(Number, Some(FVal(v))),
-            Err(_) => return Err(format!("Could not parse num: -=({})=-", s)),
+        let digits = &s[2..];
+        if digits.is_empty() {
+            return Err(self.bad_number(format!("Number literal -=({})=- has no digits", s)));
+        }
+        if let Some(c) = digits.chars().find(|c| *c != '_' && !c.is_digit(radix)) {
+            return Err(self.bad_number(format!(
+                "-=({})=- is not a base {} digit in -=({})=-",
+                c, radix, s
+            )));
+        }
+        if digits.split('_').any(str::is_empty) {
+            return Err(self
+                .bad_number(format!("Misplaced -=(_)=- in number literal -=({})=-", s))
+                .with_note("_ can only go between two digits, ex 0xFF_FF"));
+        }
+        match i64::from_str_radix(&digits.replace('_', ""), radix) {
+            Ok(v) => self.add_token_p2(Number, Some(IntVal(v))),
+            Err(_) => {
+                return Err(self
+                    .bad_number(format!("Integer literal -=({})=- is too large", s))
+                    .with_note(format!("Ints go up to {:#x}", i64::MAX)))
+            }
         }
         Ok(())
     }
+    fn bad_number(&self, msg: String) -> Diagnostic {
+        Diagnostic::error(105, msg, self.token_span())
+    }
+    // Span of the token scanned so far
+    fn token_span(&self) -> Span {
+        Span {
+            offset: self.start,
+            len: self.current - self.start,
+            line: self.start_line,
+            col: self.start_col,
+        }
+    }
+    // Zero width span at current
+    fn current_span(&self) -> Span {
+        Span {
+            offset: self.current,
+            len: 0,
+            line: self.line,
+            col: self.col,
+        }
+    }
+    // Call right after consuming a \n
+    fn newline(&mut self) {
+        self.line += 1;
+        self.col = 1;
+    }
+    // Peek n chars past current (only ever looks a couple chars ahead)
+    fn peek_at(&self, n: usize) -> char {
+        self.source[self.current..].chars().nth(n).unwrap_or('\0')
+    }
     // Peek at next value after current
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-        return self.source.chars().nth(self.current + 1).unwrap();
+        self.peek_at(1)
     }
-    // Handle string literals
-    fn string(&mut self) -> Result<(), String> {
+    // Handle string literals, also resumes after the } of an interpolation.
+    // "a ${x} b" -> StringInterp("a ") x InterpEnd StringLit(" b")
+    fn string(&mut self) -> Result<(), Diagnostic> {
+        let at = self.current_span();
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.peek() == '$' && self.peek_next() == '{' {
+                self.advance();
+                self.advance();
+                let raw = &self.source[self.start + 1..self.current - 2];
+                let v = unescape(raw, at)?;
+                self.add_token_p2(StringInterp, Some(StringVal(v)));
+                self.interps.push(0);
+                return Ok(());
+            }
+            // skip whatever is escaped so \" and \$ do not end the piece
+            if self.peek() == '\\' {
+                self.advance();
+            }
+            if !self.is_at_end() && self.advance() == '\n' {
+                self.newline();
             }
-            self.advance();
         }
         if self.is_at_end() {
-            //TODO: Make this into an error somehow
-            println!("Unterminated string at line {}", self.line);
-            return Err("Unterminated string".to_string());
+            return Err(Diagnostic::error(
+                102,
+                "Unterminated string",
+                self.token_span(),
+            ));
         }
         self.advance();
 
-        // let mut text = "".to_string();
-        // get byte rep of source so can index
-        let v = &self.source[self.start + 1..self.current - 1];
-        self.add_token_p2(StringLit, Some(StringVal(v.to_string())));
+        let raw = &self.source[self.start + 1..self.current - 1];
+        let v = unescape(raw, at)?;
+        self.add_token_p2(StringLit, Some(StringVal(v)));
         Ok(())
     }
-    // Does not modify, checks character at curr pointer
-    fn peek(&self) -> char {
-        // if at end ret null terminator
-        match self.is_at_end() {
-            true => return '\0',
-            _ => return self.source.chars().nth(self.current).unwrap(),
+    // r"..." no escapes or interpolation, text is kept exactly as written
+    fn raw_string(&mut self) -> Result<(), Diagnostic> {
+        // skip the opening quote after r
+        self.advance();
+        while self.peek() != '"' && !self.is_at_end() {
+            if self.advance() == '\n' {
+                self.newline();
+            }
+        }
+        if self.is_at_end() {
+            return Err(Diagnostic::error(
+                102,
+                "Unterminated raw string",
+                self.token_span(),
+            ));
         }
+        self.advance();
+        let v = self.source[self.start + 2..self.current - 1].to_string();
+        self.add_token_p2(StringLit, Some(StringVal(v)));
+        Ok(())
+    }
+    // """...""" multi-line, escapes work but not interpolation, common indentation gets stripped
+    fn triple_string(&mut self) -> Result<(), Diagnostic> {
+        let at = self.current_span();
+        loop {
+            if self.is_at_end() {
+                return Err(Diagnostic::error(
+                    102,
+                    "Unterminated \"\"\" string",
+                    self.token_span(),
+                ));
+            }
+            if self.peek() == '"' && self.peek_next() == '"' && self.peek_at(2) == '"' {
+                break;
+            }
+            if self.peek() == '\\' {
+                self.advance();
+            }
+            if !self.is_at_end() && self.advance() == '\n' {
+                self.newline();
+            }
+        }
+        self.advance();
+        self.advance();
+        self.advance();
+        let raw = &self.source[self.start + 3..self.current - 3];
+        // check escapes against the real source positions before the indentation moves them
+        unescape(raw, at)?;
+        let v = unescape(&dedent(raw), at)?;
+        self.add_token_p2(StringLit, Some(StringVal(v)));
+        Ok(())
+    }
+    // Does not modify, checks character at curr pointer (null terminator at the end)
+    fn peek(&self) -> char {
+        self.peek_at(0)
     }
     // Checks if next char is the expected val
     fn match_char(&mut self, expect: char) -> bool {
         // if at end there should be no other char
-        if self.is_at_end() {
-            return false;
-        }
-        // if not expected val ret false
-        if self.source.chars().nth(self.current).unwrap() as char != expect {
+        if self.is_at_end() || self.peek() != expect {
             return false;
-        } else {
-            // incr curr pointer
-            self.current += 1;
-            return true;
         }
+        self.advance();
+        true
     }
-    // Advances string index of the source
+    // Moves past the current char, current is a byte offset so step by the char's utf8 width
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+        let c = self.peek();
+        self.current += c.len_utf8();
+        self.col += 1;
         c
     }
     // First add_token call (not too sure but book says to do)
@@ -271,7 +721,7 @@ impl Scanner {
             token_type,
             lexeme: text,
             literal,
-            line_num: self.line,
+            span: self.token_span(),
         });
     }
 }
@@ -283,13 +733,21 @@ pub enum TokenType {
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Amp,
+    Pipe,
+    Caret,
+    Tilde,
     // Single/Double Char
     Bang,
     BangEqual,
@@ -299,13 +757,30 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    StarStar,
+    SlashSlash,
+    LessLess,
+    GreaterGreater,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    PlusPlus,
+    MinusMinus,
     // Literals
     Identifier,
     StringLit,
+    // string piece that has a ${ ... } right after it
+    StringInterp,
+    // the } that closes a ${ ... }
+    InterpEnd,
     Number,
     // Keywords
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -326,6 +801,7 @@ pub enum TokenType {
 
 use crate::TokenType::*;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone)]
 pub enum LiteralVal {
     IntVal(i64),
@@ -336,12 +812,39 @@ pub enum LiteralVal {
 
 use LiteralVal::*;
 
+// Where something is in the source, offset/len are bytes, line/col (1 based) are for people
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    // Span covering from the start of self to the end of other
+    pub fn to(self, other: Span) -> Span {
+        let end = (other.offset + other.len).max(self.offset + self.len);
+        Span {
+            len: end - self.offset,
+            ..self
+        }
+    }
+}
+
+// Shows as "line 3, col 7" in error messages
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.col)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: Option<LiteralVal>,
-    pub line_num: usize,
+    pub span: Span,
 }
 
 // Need this to display the tokens w/ to_string
@@ -357,13 +860,13 @@ impl Token {
         token_type: TokenType,
         lexeme: String,
         literal: Option<LiteralVal>,
-        line_num: usize,
+        span: Span,
     ) -> Self {
         Self {
             token_type,
             lexeme,
             literal,
-            line_num,
+            span,
         }
     }
     // Converts to string format
@@ -437,3 +940,137 @@ impl Token {
 //         assert_eq!(scan.tokens.len(), 13);
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Value of the string token the source scans to
+    fn string(src: &str) -> String {
+        let tokens = Scanner::new(src).scan_tokens().unwrap();
+        match &tokens[0].literal {
+            Some(StringVal(v)) => v.clone(),
+            other => panic!("not a string: {:?}", other),
+        }
+    }
+    fn err(src: &str) -> Diagnostic {
+        Scanner::new(src).scan_tokens().err().unwrap().iter().next().unwrap().clone()
+    }
+    // Literal of the number token the source scans to
+    fn number(src: &str) -> LiteralVal {
+        let tokens = Scanner::new(src).scan_tokens().unwrap();
+        assert_eq!(tokens[0].token_type, Number, "{}", src);
+        tokens[0].literal.clone().unwrap()
+    }
+    // Token types without the Eof
+    fn types(src: &str) -> Vec<TokenType> {
+        let tokens = Scanner::new(src).scan_tokens().unwrap();
+        tokens.iter().map(|t| t.token_type).filter(|t| *t != Eof).collect()
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(types("1 /* a /* nested */ one */ + 2 # rest of line"), [Number, Plus, Number]);
+        assert_eq!(types("# only a comment\n/*\n*/"), []);
+        // // is floor division now, not a comment
+        assert_eq!(types("7 // 2"), [Number, SlashSlash, Number]);
+        assert_eq!(err("1 /* never closed /* */").code, 107);
+    }
+
+    #[test]
+    fn number_literal_forms() {
+        assert!(matches!(number("0xFF"), IntVal(255)));
+        assert!(matches!(number("0b1010_1010"), IntVal(170)));
+        assert!(matches!(number("0o17"), IntVal(15)));
+        assert!(matches!(number("1_000_000"), IntVal(1000000)));
+        assert!(matches!(number("6.02e23"), FVal(x) if x == 6.02e23));
+        assert!(matches!(number("25E-1"), FVal(x) if x == 2.5));
+        assert!(matches!(number("1e3"), FVal(x) if x == 1000.0));
+    }
+
+    #[test]
+    fn malformed_number_literals() {
+        for src in ["0x", "0b102", "1__0", "1_", "1_.5", "1e", "1e+", "12abc", "0x_1", "1e999"] {
+            let e = err(src);
+            assert_eq!(e.code, 105, "{}", src);
+            // points at the whole literal
+            assert_eq!((e.span.col, e.span.len), (1, src.len()), "{}", src);
+        }
+    }
+
+    #[test]
+    fn interpolation_tokens() {
+        let tokens = Scanner::new("\"a ${x} b\"").scan_tokens().unwrap();
+        let pieces: Vec<(TokenType, &str)> =
+            tokens.iter().map(|t| (t.token_type, t.lexeme.as_str())).collect();
+        assert_eq!(
+            pieces,
+            [
+                (StringInterp, "\"a ${"),
+                (Identifier, "x"),
+                (InterpEnd, "}"),
+                (StringLit, "} b\""),
+                (Eof, ""),
+            ]
+        );
+        assert_eq!(string("\"a ${x} b\""), "a ");
+        // a string inside ${ } is its own literal, the outer one picks up after the }
+        assert_eq!(
+            types("\"${\"in ${y}\"} out\""),
+            [StringInterp, StringInterp, Identifier, InterpEnd, StringLit, InterpEnd, StringLit]
+        );
+        // braces of blocks or maps inside ${ } do not close the interpolation
+        assert_eq!(
+            types("\"${ {\"k\": 1} }\""),
+            [StringInterp, LBrace, StringLit, Colon, Number, RBrace, InterpEnd, StringLit]
+        );
+        assert_eq!(types("\"${ {} }!\""), [StringInterp, LBrace, RBrace, InterpEnd, StringLit]);
+        // \$ keeps a plain $
+        assert_eq!(types("\"\\${x}\""), [StringLit]);
+    }
+
+    #[test]
+    fn unterminated_interpolation() {
+        assert_eq!(err("\"a ${x").code, 106);
+        // the } of the inner braces is not the end, so the last " opens a new string
+        assert_eq!(err("\"a ${ {x} \"").code, 102);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(string(r#""a\n\t\r\\\"\$b""#), "a\n\t\r\\\"$b");
+        assert_eq!(string(r#""\u{1F600}\u{e9}""#), "\u{1F600}\u{e9}");
+        // unknown escapes point at the backslash, past any multi-byte text before it
+        let e = err("\"é \\q\"");
+        assert_eq!((e.code, e.span.line, e.span.col, e.span.len), (103, 1, 4, 2));
+        let e = err("\"\n\\u{110000}\"");
+        assert_eq!((e.code, e.span.line, e.span.col), (104, 2, 1));
+        assert_eq!(err(r#""\u{}""#).code, 104);
+        assert_eq!(err(r#""\u{1F600""#).code, 104);
+    }
+
+    #[test]
+    fn raw_strings_keep_everything() {
+        assert_eq!(string(r#"r"C:\no\escapes ${x}""#), r"C:\no\escapes ${x}");
+        assert_eq!(err("r\"never closed").code, 102);
+    }
+
+    #[test]
+    fn triple_strings_dedent() {
+        let src = "\"\"\"\n    Dear reader,\n      indented\n\n    end\n    \"\"\"";
+        assert_eq!(string(src), "Dear reader,\n  indented\n\nend");
+        // tabs count as indentation too, escapes still work
+        assert_eq!(string("\"\"\"\n\ta\\tb\n\t\tc\n\"\"\""), "a\tb\n\tc");
+        // a line of only non-ASCII whitespace is blank, not something to slice into
+        assert_eq!(string("\"\"\"\n a\n\u{a0}\n b\n\"\"\""), "a\n\nb");
+        assert_eq!(string("\"\"\"\n  a\n \u{a0}x\n\"\"\""), " a\n\u{a0}x");
+    }
+
+    #[test]
+    fn triple_string_errors_use_source_columns() {
+        // the escape is reported where it is written, not where dedenting moved it
+        let e = err("\"\"\"\n        ok\n        bad \\q\n\"\"\"");
+        assert_eq!((e.code, e.span.line, e.span.col), (103, 3, 13));
+        assert_eq!(err("\"\"\"\nnever closed").code, 102);
+    }
+}
@@ -1,3 +1,4 @@
+use std::rc::Rc;
 use crate::expr::Expr;
 use crate::Token;
 
@@ -11,5 +12,41 @@ pub enum Statement {
     Var {
         t: Token,
         init: Expr
+    },
+    Block {
+        statements: Vec<Statement>
+    },
+    If {
+        cond: Expr,
+        then_b: Box<Statement>,
+        else_b: Option<Box<Statement>>
+    },
+    // step is only set by for loops, it runs after every pass (even on continue)
+    While {
+        cond: Expr,
+        body: Box<Statement>,
+        step: Option<Expr>
+    },
+    Break {
+        t: Token
+    },
+    Continue {
+        t: Token
+    },
+    // body is shared with every function value made from this declaration
+    Function {
+        name: Token,
+        params: Vec<Token>,
+        body: Rc<Vec<Statement>>
+    },
+    Return {
+        t: Token,
+        val: Option<Expr>
+    },
+    // methods are all Statement::Function, superclass is always an Expr::Variable
+    Class {
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<Statement>
     }
 }